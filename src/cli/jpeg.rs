@@ -27,3 +27,51 @@ impl From<CompressProfile> for JINT_COMPRESS_PROFILE_VALUE {
         }
     }
 }
+
+#[derive(ValueEnum, Clone, Debug, Default)]
+pub enum Subsampling {
+    #[default]
+    #[value(name = "420")]
+    S420,
+    #[value(name = "422")]
+    S422,
+    #[value(name = "444")]
+    S444,
+}
+
+impl Display for Subsampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::S420 => write!(f, "420"),
+            Self::S422 => write!(f, "422"),
+            Self::S444 => write!(f, "444"),
+        }
+    }
+}
+
+impl From<Subsampling> for s739::options::Subsampling {
+    fn from(value: Subsampling) -> Self {
+        match value {
+            Subsampling::S420 => Self::S420,
+            Subsampling::S422 => Self::S422,
+            Subsampling::S444 => Self::S444,
+        }
+    }
+}
+
+/// 64 comma-separated values between 1 and 255
+pub fn parse_quant_table(value: &str) -> Result<[u16; 64], String> {
+    let values = value
+        .split(',')
+        .map(|value| match value.trim().parse::<u16>() {
+            Ok(value @ 1..=255) => Ok(value),
+            _ => Err(format!(
+                "invalid quantization value {value:?}, expected 1-255"
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = values.len();
+    values
+        .try_into()
+        .map_err(|_| format!("expected 64 quantization values, got {len}"))
+}
@@ -1,4 +1,5 @@
 mod jpeg;
+mod mode;
 mod png;
 
 use std::path::PathBuf;
@@ -28,32 +29,48 @@ pub struct ExtraArgs {
     /// Secret key
     #[arg(short, long, value_hint = ValueHint::Other)]
     key: Option<String>,
+    /// Where to hide data: pixel/DCT coef LSB, metadata (JPEG APP15, PNG private chunk), JPEG COM segments, after the image end, or text zero-width characters/trailing whitespace
+    #[arg(long, default_value_t = mode::Mode::Lsb)]
+    mode: mode::Mode,
     /// Skip some DCT coefs for JPEG
     #[arg(long)]
     selective: bool,
     /// Depth (least bit to use)
-    #[arg(long, default_value_t = 0, value_parser = 0..=7)]
+    #[arg(long, default_value_t = 0, value_parser = 0..=15)]
     depth: i64,
     /// Number of bits per single image unit (pixel/DCT coef)
-    #[arg(long, default_value_t = 1, value_parser = 1..=8)]
+    #[arg(long, default_value_t = 1, value_parser = 1..=16)]
     bits: i64,
     /// JPEG component index
     #[arg(long)]
     jpeg_comp: Option<u8>,
+    /// Image/audio channel or video plane (Y, U, V) indices to use, comma-separated (e.g. 0,1,2 to exclude alpha)
+    #[arg(long, value_delimiter = ',')]
+    channels: Option<Vec<u8>>,
+    /// Skip fully transparent and fully opaque pixels, alpha of the others never becomes 0 or max
+    #[arg(long)]
+    skip_transparent: bool,
     /// Overwrite calculated max step
     #[arg(long)]
     max_step: Option<usize>,
+    /// Cover format, detected from content by default, text only by name or with the text modes (jpeg, png, bmp, webp, gif, tiff, pnm, qoi, wav, flac, y4m, text)
+    #[arg(long, value_hint = ValueHint::Other)]
+    format: Option<String>,
 }
 
 impl From<ExtraArgs> for s739::options::ExtraArgs {
     fn from(value: ExtraArgs) -> Self {
         Self {
             key: value.key,
+            mode: value.mode.into(),
             selective: value.selective,
             depth: value.depth as usize,
             bits: value.bits as usize,
             jpeg_comp: value.jpeg_comp,
+            channels: value.channels,
+            skip_transparent: value.skip_transparent,
             max_step: value.max_step,
+            format: value.format,
         }
     }
 }
@@ -63,13 +80,16 @@ pub struct EncodeArgs {
     /// Input file
     #[arg(short, long, value_hint = ValueHint::FilePath)]
     pub input: PathBuf,
-    /// Output file
+    /// Output file, a known extension converts the cover to that format (lossless only)
     #[arg(short, long, value_hint = ValueHint::FilePath)]
     pub output: PathBuf,
     #[command(flatten)]
     pub image_opts: ImageOptions,
     #[command(flatten)]
     pub data: Data,
+    /// Data size in bytes, required to stream stdin without buffering it
+    #[arg(long, conflicts_with_all = ["text", "file"])]
+    pub size: Option<usize>,
     #[command(flatten)]
     pub extra_args: ExtraArgs,
 }
@@ -99,6 +119,9 @@ pub struct PngOptions {
     /// PNG filter type
     #[arg(long = "png-filter", default_value_t = png::FilterType::Adaptive)]
     filter: png::FilterType,
+    /// Drop ancillary chunks (metadata) of the input PNG
+    #[arg(id = "png_strip", long = "png-strip")]
+    strip: bool,
 }
 
 impl From<PngOptions> for s739::options::PngOptions {
@@ -106,6 +129,7 @@ impl From<PngOptions> for s739::options::PngOptions {
         s739::options::PngOptions {
             compression: value.compression.into(),
             filter: value.filter.into(),
+            strip: value.strip,
         }
     }
 }
@@ -115,12 +139,48 @@ pub struct JpegOptions {
     /// MozJPEG compression profile
     #[arg(long = "jpeg-compress-profile", default_value_t = jpeg::CompressProfile::Max)]
     compress_profile: jpeg::CompressProfile,
+    /// Drop APPn and COM markers (EXIF, ICC profile, XMP, comments) of the input JPEG
+    #[arg(id = "jpeg_strip", long = "jpeg-strip")]
+    strip: bool,
+    /// Keep progressive/baseline mode, restart interval and Huffman optimization of the input JPEG
+    #[arg(long = "jpeg-mimic-source")]
+    mimic_source: bool,
+    /// JPEG quality when compressing pixels (non-JPEG cover or --jpeg-requantize) [default: 75 without --jpeg-quant-table]
+    #[arg(long = "jpeg-quality", value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+    /// Custom JPEG quantization table, 64 comma-separated values in row-major order used as is unless --jpeg-quality is given, repeat for chroma
+    #[arg(long = "jpeg-quant-table", value_parser = jpeg::parse_quant_table)]
+    quant_tables: Vec<[u16; 64]>,
+    /// JPEG chroma subsampling when compressing pixels [default: 420]
+    #[arg(long = "jpeg-subsampling")]
+    subsampling: Option<jpeg::Subsampling>,
+    /// Enable/disable JPEG trellis quantization of AC coefs (profile default if unset)
+    #[arg(long = "jpeg-trellis")]
+    trellis: Option<bool>,
+    /// Enable/disable JPEG trellis quantization of DC coefs (profile default if unset)
+    #[arg(long = "jpeg-trellis-dc")]
+    trellis_dc: Option<bool>,
+    /// Number of JPEG trellis quantization loops
+    #[arg(long = "jpeg-trellis-loops")]
+    trellis_loops: Option<u8>,
+    /// Recompress a JPEG cover from its pixels with the quality and quantization options
+    #[arg(long = "jpeg-requantize")]
+    requantize: bool,
 }
 
 impl From<JpegOptions> for s739::options::JpegOptions {
     fn from(value: JpegOptions) -> Self {
         s739::options::JpegOptions {
             compress_profile: value.compress_profile.into(),
+            strip: value.strip,
+            mimic_source: value.mimic_source,
+            quality: value.quality,
+            quant_tables: value.quant_tables,
+            subsampling: value.subsampling.map(Into::into),
+            trellis: value.trellis,
+            trellis_dc: value.trellis_dc,
+            trellis_loops: value.trellis_loops,
+            requantize: value.requantize,
         }
     }
 }
@@ -134,7 +194,7 @@ pub struct Data {
     /// Encode file
     #[arg(short, long, value_hint = ValueHint::FilePath)]
     pub file: Option<PathBuf>,
-    /// Read data from stdin
+    /// Read data from stdin, buffered whole unless --size is given
     #[arg(short, long)]
     pub stdin: bool,
 }
@@ -0,0 +1,40 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Debug, Default)]
+pub enum Mode {
+    #[default]
+    Lsb,
+    Metadata,
+    Comment,
+    Trailing,
+    ZeroWidth,
+    Whitespace,
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lsb => write!(f, "lsb"),
+            Self::Metadata => write!(f, "metadata"),
+            Self::Comment => write!(f, "comment"),
+            Self::Trailing => write!(f, "trailing"),
+            Self::ZeroWidth => write!(f, "zero-width"),
+            Self::Whitespace => write!(f, "whitespace"),
+        }
+    }
+}
+
+impl From<Mode> for s739::options::Mode {
+    fn from(value: Mode) -> Self {
+        match value {
+            Mode::Lsb => Self::Lsb,
+            Mode::Metadata => Self::Metadata,
+            Mode::Comment => Self::Comment,
+            Mode::Trailing => Self::Trailing,
+            Mode::ZeroWidth => Self::ZeroWidth,
+            Mode::Whitespace => Self::Whitespace,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::apng::Animation;
+use crate::utils::lsb::Lsb;
+
+use super::LsbDecoder;
+
+/// Animated PNG, reads data spread over pixels of all frames
+pub struct ApngDecoder {
+    frames: Vec<DynamicImage>,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl ApngDecoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let frames = Animation::decode(image_buffer)?.frames;
+        let size = utils::raster::frames_check(&frames, &extra)?;
+        Ok(Self {
+            frames,
+            size,
+            extra,
+        })
+    }
+}
+
+impl LsbDecoder for ApngDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::raster::frames_units(&self.frames, &self.extra, seek, max_step)
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+
+use crate::options::{ExtraArgs, Mode};
+use crate::utils;
+
+use super::{DataReader, Decoder};
+
+/// Byte runs of the cover that may hold an envelope stored in `mode`,
+/// failing on covers or modes the format doesn't support
+pub type Extract = fn(&[u8], Mode) -> Result<Vec<Vec<u8>>>;
+
+/// Modes looked through by [`extract_jpeg`] in LSB mode
+pub const JPEG_MODES: &[Mode] = &[Mode::Metadata, Mode::Comment, Mode::Trailing];
+/// Modes looked through by [`extract_png`] in LSB mode
+pub const PNG_MODES: &[Mode] = &[Mode::Metadata, Mode::Trailing];
+
+/// Consecutive APP15/COM segments, or the bytes after EOI
+pub fn extract_jpeg(buffer: &[u8], mode: Mode) -> Result<Vec<Vec<u8>>> {
+    let (segments, end) = utils::jpeg::segments(buffer)?;
+    if mode == Mode::Trailing {
+        return Ok(vec![buffer[end..].to_vec()]);
+    }
+
+    let marker = utils::jpeg::container_marker(mode);
+    // envelope may be split over consecutive segments
+    Ok((0..segments.len())
+        .filter(|&i| segments[i].marker == marker)
+        .map(|i| {
+            segments[i..]
+                .iter()
+                .take_while(|segment| segment.marker == marker)
+                .flat_map(|segment| &buffer[segment.data.clone()])
+                .copied()
+                .collect()
+        })
+        .collect())
+}
+
+/// Consecutive private ancillary chunks, or the bytes after IEND
+pub fn extract_png(buffer: &[u8], mode: Mode) -> Result<Vec<Vec<u8>>> {
+    let (chunks, end) = utils::png::split_chunks(buffer)?;
+    match mode {
+        Mode::Trailing => Ok(vec![buffer[end..].to_vec()]),
+        Mode::Comment => bail!("comment mode isn't supported for PNG"),
+        _ => Ok((0..chunks.len())
+            .filter(|&i| chunks[i].kind == utils::png::PRIVATE_CHUNK)
+            .map(|i| {
+                chunks[i..]
+                    .iter()
+                    .take_while(|chunk| chunk.kind == utils::png::PRIVATE_CHUNK)
+                    .flat_map(|chunk| &chunk.data)
+                    .copied()
+                    .collect()
+            })
+            .collect()),
+    }
+}
+
+/// Extracts the payload envelope from the cover through `extract`
+pub struct ContainerDecoder {
+    data: Vec<u8>,
+    extra: ExtraArgs,
+}
+
+impl ContainerDecoder {
+    /// Look for an envelope opened by the key, in the selected mode or in all
+    /// of `modes` for LSB mode, where nothing found means pixel embedding
+    pub fn detect(
+        image_buffer: &[u8],
+        extra: &ExtraArgs,
+        modes: &[Mode],
+        extract: Extract,
+    ) -> Result<Option<Self>> {
+        let modes = match extra.mode {
+            Mode::Lsb => modes.to_vec(),
+            mode => vec![mode],
+        };
+
+        for mode in modes {
+            let runs = match extract(image_buffer, mode) {
+                Ok(runs) => runs,
+                // left to the pixel decoder, which may cope with it
+                Err(_) if extra.mode == Mode::Lsb => return Ok(None),
+                Err(err) => return Err(err),
+            };
+            let found = runs
+                .iter()
+                .find_map(|run| utils::envelope::open(run, extra.key.clone()));
+            if let Some(data) = found {
+                return Ok(Some(Self {
+                    data,
+                    extra: extra.clone(),
+                }));
+            }
+        }
+
+        if extra.mode != Mode::Lsb {
+            bail!("no data found in container");
+        }
+        Ok(None)
+    }
+}
+
+impl Decoder for ContainerDecoder {
+    fn total_size(&self) -> usize {
+        self.data.len() << 3
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn reader(&self) -> Result<DataReader<'_>> {
+        Ok(DataReader::from_bytes(&self.data))
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::flac::Flac;
+use crate::utils::lsb::Lsb;
+use crate::utils::pcm::Pcm;
+
+use super::LsbDecoder;
+
+/// FLAC, extracts LSB of decoded samples of the selected channels
+pub struct FlacDecoder {
+    pcm: Pcm,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl FlacDecoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let pcm = Flac::decode(buffer)?.pcm;
+        let size = utils::pcm::check(&pcm, &extra)?;
+        Ok(Self { pcm, size, extra })
+    }
+}
+
+impl LsbDecoder for FlacDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::pcm::units(&self.pcm, &self.extra, seek, max_step)
+    }
+}
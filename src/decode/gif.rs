@@ -0,0 +1,62 @@
+use anyhow::{ensure, Result};
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::gif::Animation;
+use crate::utils::lsb::Lsb;
+
+use super::LsbDecoder;
+
+/// GIF (animated too), extracts parity of luminance-ordered palette ranks of
+/// every frame
+pub struct GifDecoder {
+    ranks: Vec<u8>,
+    extra: ExtraArgs,
+}
+
+impl GifDecoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        ensure!(
+            extra.depth == 0 && extra.bits == 1,
+            "invalid depth and bits for palette image: {} + {}, only 0 + 1 is supported",
+            extra.depth,
+            extra.bits
+        );
+        let animation = Animation::decode(image_buffer)?;
+        let orders = animation.orders()?;
+        let mut ranks = Vec::new();
+        for (frame, order) in animation.frames.iter().zip(&orders) {
+            for &index in frame.buffer.iter() {
+                let rank = order.rank(index)?;
+                if order.is_usable(rank) {
+                    ranks.push(rank);
+                }
+            }
+        }
+        ensure!(ranks.len() > 32, "GIF is too small");
+        Ok(Self { ranks, extra })
+    }
+}
+
+impl LsbDecoder for GifDecoder {
+    fn total_size(&self) -> usize {
+        (self.ranks.len() - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        Ok(utils::iter::units(
+            self.ranks.iter(),
+            self.extra.key.clone(),
+            seek,
+            max_step,
+        ))
+    }
+}
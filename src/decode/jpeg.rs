@@ -1,14 +1,11 @@
-use anyhow::{bail, ensure, Result};
-use bitvec::prelude::*;
-use mozjpeg_sys::{jpeg_decompress_struct, jpeg_destroy_decompress, jpeg_finish_decompress};
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
-use rand_seeder::Seeder;
+use anyhow::{ensure, Result};
+use mozjpeg_sys::{jpeg_decompress_struct, jpeg_finish_decompress};
 
 use crate::options::ExtraArgs;
 use crate::utils;
+use crate::utils::lsb::Lsb;
 
-use super::Decoder;
+use super::LsbDecoder;
 
 pub struct JpegDecoder {
     cinfo: jpeg_decompress_struct,
@@ -44,7 +41,7 @@ impl JpegDecoder {
     }
 }
 
-impl Decoder for JpegDecoder {
+impl LsbDecoder for JpegDecoder {
     fn total_size(&self) -> usize {
         (self.total_size - 32) * self.extra().bits
     }
@@ -53,29 +50,18 @@ impl Decoder for JpegDecoder {
         &self.extra
     }
 
-    fn read(&self, data: &mut BitSlice<u8>, seek: usize, max_step: usize) -> Result<()> {
-        let mut image_iter = self.blocks.iter(self.extra().clone());
-
-        let mut rng = ChaCha20Rng::from_seed(Seeder::from(self.extra.key.clone()).make_seed());
-        let mut data_iter = data.iter_mut();
-        let mask = !(u16::MAX << self.extra.bits) as i16;
-        let shift = u16::BITS as usize - self.extra().bits;
-
-        if seek > 0 {
-            image_iter.nth(seek - 1);
-        }
-
-        while let Some(coef) = image_iter.nth(utils::iter::rand_step(&mut rng, max_step)) {
-            let value = ((*coef >> self.extra().depth) & mask).reverse_bits() >> shift;
-            if utils::iter::set_n_bits(value, &mut data_iter, self.extra().bits).is_err() {
-                return Ok(());
-            }
-        }
-
-        if data_iter.next().is_some() {
-            bail!("image ended but data not");
-        }
-        Ok(())
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        let image_iter = self.blocks.iter(self.extra.clone());
+        Ok(utils::iter::units(
+            image_iter,
+            self.extra.key.clone(),
+            seek,
+            max_step,
+        ))
     }
 }
 
@@ -83,7 +69,7 @@ impl Drop for JpegDecoder {
     fn drop(&mut self) {
         unsafe {
             jpeg_finish_decompress(&mut self.cinfo);
-            jpeg_destroy_decompress(&mut self.cinfo);
+            utils::jpeg::destroy_decompress(&mut self.cinfo);
         }
     }
 }
@@ -1,36 +1,71 @@
+pub mod apng;
+pub mod container;
+pub mod flac;
+pub mod gif;
 pub mod jpeg;
 pub mod png;
+pub mod raster;
+mod reader;
+pub mod text;
+pub mod tiff;
+pub mod wav;
+pub mod y4m;
 
+use std::io::Read;
 use std::path::PathBuf;
 
 use anyhow::{bail, ensure, Result};
 use bitvec::bits;
 use bitvec::prelude::*;
 
+use crate::format;
 use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
 
-use self::jpeg::JpegDecoder;
-use self::png::PngDecoder;
+pub use self::reader::DataReader;
 
+/// Stego file holding a payload, read through `reader`
 pub trait Decoder {
-    fn read(&self, data: &mut BitSlice<u8>, seek: usize, max_step: usize) -> Result<()>;
+    fn reader(&self) -> Result<DataReader<'_>>;
     fn total_size(&self) -> usize;
     fn extra(&self) -> &ExtraArgs;
 
     fn read_data(&self) -> Result<Vec<u8>> {
-        let size = bits![mut u8, Lsb0; 0u8; 32];
-        self.read(size, 0, 0)?;
-        let size: usize = size.load();
+        let mut reader = self.reader()?;
+        let mut data = Vec::with_capacity(reader.remaining());
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
 
-        if self.extra().max_step.is_none() {
-            self.check_size(size)?;
-        }
+/// Stego file holding the payload bits in LSB units such as pixel channels,
+/// DCT coefs or samples, after a 32-bit size header
+pub trait LsbDecoder {
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>>;
+    fn total_size(&self) -> usize;
+    fn extra(&self) -> &ExtraArgs;
 
-        let (data_size, max_step) = self.data_size(size)?;
-        let mut data = vec![0u8; data_size];
-        self.read(data.view_bits_mut(), 32, max_step)?;
+    fn read(&self, data: &mut BitSlice<u8>, seek: usize, max_step: usize) -> Result<()> {
+        let extra = self.extra();
+        let shift = u16::BITS as usize - extra.bits;
+        let mut data_iter = data.iter_mut();
 
-        Ok(data)
+        for unit in self.units(seek, max_step)? {
+            let value = unit.get_bits(extra.depth, extra.bits).reverse_bits() >> shift;
+            if utils::iter::set_n_bits(value, &mut data_iter, extra.bits).is_err() {
+                return Ok(());
+            }
+        }
+
+        if data_iter.next().is_some() {
+            bail!("image ended but data not");
+        }
+        Ok(())
     }
 
     fn check_size(&self, data_size: usize) -> Result<()> {
@@ -56,14 +91,35 @@ pub trait Decoder {
     }
 }
 
-pub fn new_decoder(input: PathBuf, extra_args: ExtraArgs) -> Result<Box<dyn Decoder>> {
-    let image_buf = std::fs::read(input)?;
-    match image::guess_format(&image_buf)? {
-        image::ImageFormat::Png => Ok(Box::new(PngDecoder::new(
-            image::load_from_memory(&image_buf)?,
-            extra_args,
-        )?)),
-        image::ImageFormat::Jpeg => Ok(Box::new(JpegDecoder::new(&image_buf, extra_args)?)),
-        _ => bail!("invalid image format"),
+impl<T: LsbDecoder> Decoder for T {
+    fn reader(&self) -> Result<DataReader<'_>> {
+        let size = bits![mut u8, Lsb0; 0u8; 32];
+        self.read(size, 0, 0)?;
+        let size: usize = size.load();
+
+        if LsbDecoder::extra(self).max_step.is_none() {
+            self.check_size(size)?;
+        }
+
+        let (data_size, max_step) = self.data_size(size)?;
+        Ok(DataReader::new(
+            self.units(32, max_step)?,
+            data_size,
+            LsbDecoder::extra(self),
+        ))
+    }
+
+    fn total_size(&self) -> usize {
+        LsbDecoder::total_size(self)
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        LsbDecoder::extra(self)
     }
 }
+
+pub fn new_decoder(input: PathBuf, extra_args: ExtraArgs) -> Result<Box<dyn Decoder>> {
+    let buffer = std::fs::read(input)?;
+    let format = format::find_cover(&buffer, &extra_args)?;
+    (format.decoder)(&buffer, extra_args)
+}
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+use super::LsbDecoder;
+
+/// Decoded raster image (PNG, BMP, ...), extracts LSB of channel values
+pub struct RasterDecoder {
+    image: DynamicImage,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl RasterDecoder {
+    pub fn new(image: DynamicImage, extra: ExtraArgs) -> Result<Self> {
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self { image, size, extra })
+    }
+}
+
+impl LsbDecoder for RasterDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::raster::units(&self.image, &self.extra, seek, max_step)
+    }
+}
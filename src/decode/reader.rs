@@ -0,0 +1,109 @@
+use std::io::{self, Read};
+
+use crate::options::ExtraArgs;
+use crate::utils::lsb::Lsb;
+
+/// Streaming payload reader, extracts bits from image units on demand
+pub struct DataReader<'a> {
+    source: Source<'a>,
+    remaining: usize,
+}
+
+enum Source<'a> {
+    Units {
+        units: Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>,
+        depth: usize,
+        bits: usize,
+        value: u16,
+        left: usize,
+    },
+    Bytes(&'a [u8]),
+}
+
+impl<'a> DataReader<'a> {
+    pub(crate) fn new(
+        units: Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>,
+        size: usize,
+        extra: &ExtraArgs,
+    ) -> Self {
+        Self {
+            source: Source::Units {
+                units,
+                depth: extra.depth,
+                bits: extra.bits,
+                value: 0,
+                left: 0,
+            },
+            remaining: size,
+        }
+    }
+
+    /// Reader over raw payload bytes, for containers storing data as is
+    pub(crate) fn from_bytes(data: &'a [u8]) -> Self {
+        Self {
+            source: Source::Bytes(data),
+            remaining: data.len(),
+        }
+    }
+
+    /// Number of payload bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl Source<'_> {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        match self {
+            Source::Units { .. } => {
+                let mut byte = 0;
+                for i in 0..8 {
+                    if self.next_bit()? {
+                        byte |= 1 << i;
+                    }
+                }
+                Ok(byte)
+            }
+            Source::Bytes(data) => {
+                let (&byte, rest) = data.split_first().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "container ended but data not")
+                })?;
+                *data = rest;
+                Ok(byte)
+            }
+        }
+    }
+
+    fn next_bit(&mut self) -> io::Result<bool> {
+        let Source::Units {
+            units,
+            depth,
+            bits,
+            value,
+            left,
+        } = self
+        else {
+            unreachable!()
+        };
+        if *left == 0 {
+            let unit = units.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "image ended but data not")
+            })?;
+            *value = unit.get_bits(*depth, *bits);
+            *left = *bits;
+        }
+        *left -= 1;
+        Ok((*value >> *left) & 1 == 1)
+    }
+}
+
+impl Read for DataReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining);
+        for byte in buf[..len].iter_mut() {
+            *byte = self.source.read_byte()?;
+            self.remaining -= 1;
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,51 @@
+use anyhow::{bail, Result};
+
+use crate::options::{ExtraArgs, Mode};
+use crate::utils;
+
+use super::{DataReader, Decoder};
+
+/// Plain text, extracts the payload envelope from zero-width characters or
+/// trailing whitespace
+pub struct TextDecoder {
+    data: Vec<u8>,
+    extra: ExtraArgs,
+}
+
+impl TextDecoder {
+    /// Look for an envelope opened by the key, in the selected mode or in
+    /// both of them for LSB mode
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let modes = match extra.mode {
+            Mode::Lsb => vec![Mode::ZeroWidth, Mode::Whitespace],
+            mode @ (Mode::ZeroWidth | Mode::Whitespace) => vec![mode],
+            _ => bail!("only zero-width and whitespace modes are supported for text"),
+        };
+        let text = std::str::from_utf8(buffer)?;
+
+        for mode in modes {
+            let bytes = match mode {
+                Mode::Whitespace => utils::text::extract_whitespace(text),
+                _ => utils::text::extract_zero_width(text),
+            };
+            if let Some(data) = utils::envelope::open(&bytes, extra.key.clone()) {
+                return Ok(Self { data, extra });
+            }
+        }
+        bail!("no data found in text")
+    }
+}
+
+impl Decoder for TextDecoder {
+    fn total_size(&self) -> usize {
+        self.data.len() << 3
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn reader(&self) -> Result<DataReader<'_>> {
+        Ok(DataReader::from_bytes(&self.data))
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::tiff::Pages;
+
+use super::LsbDecoder;
+
+/// Multi-page TIFF, reads data spread over pixels of all pages
+pub struct TiffDecoder {
+    frames: Vec<DynamicImage>,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl TiffDecoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let frames = Pages::decode(image_buffer)?.frames;
+        let size = utils::raster::frames_check(&frames, &extra)?;
+        Ok(Self {
+            frames,
+            size,
+            extra,
+        })
+    }
+}
+
+impl LsbDecoder for TiffDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::raster::frames_units(&self.frames, &self.extra, seek, max_step)
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::wav::Wav;
+
+use super::LsbDecoder;
+
+/// 16-bit and 24-bit PCM WAV, extracts LSB of samples of the selected channels
+pub struct WavDecoder {
+    wav: Wav,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl WavDecoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let wav = Wav::decode(buffer)?;
+        let size = utils::pcm::check(&wav.pcm, &extra)?;
+        Ok(Self { wav, size, extra })
+    }
+}
+
+impl LsbDecoder for WavDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::pcm::units(&self.wav.pcm, &self.extra, seek, max_step)
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::y4m::Video;
+
+use super::LsbDecoder;
+
+/// YUV4MPEG2 video, extracts LSB of samples of the selected planes of all frames
+pub struct Y4mDecoder {
+    video: Video,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl Y4mDecoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let video = Video::decode(buffer)?;
+        let size = utils::y4m::check(&video, &extra)?;
+        Ok(Self { video, size, extra })
+    }
+}
+
+impl LsbDecoder for Y4mDecoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> &ExtraArgs {
+        &self.extra
+    }
+
+    fn units(
+        &self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &dyn Lsb> + '_>> {
+        utils::y4m::units(&self.video, &self.extra, seek, max_step)
+    }
+}
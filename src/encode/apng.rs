@@ -0,0 +1,49 @@
+use anyhow::Result;
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::apng::Animation;
+use crate::utils::lsb::Lsb;
+
+use super::LsbEncoder;
+
+/// Animated PNG, spreads data over pixels of all frames
+pub struct ApngEncoder {
+    animation: Animation,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl ApngEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let animation = Animation::decode(image_buffer)?;
+        let size = utils::raster::frames_check(&animation.frames, &extra)?;
+        Ok(Self {
+            animation,
+            size,
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for ApngEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::frames_units_mut(&mut self.animation.frames, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>> {
+        self.animation.encode(&image_opts.png)
+    }
+}
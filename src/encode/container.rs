@@ -0,0 +1,116 @@
+use anyhow::{bail, ensure, Result};
+
+use crate::options::{ExtraArgs, ImageOptions, Mode};
+use crate::utils;
+
+use super::{DataWriter, Encoder};
+
+/// Max data length of a single JPEG marker segment
+const JPEG_SEGMENT_SIZE: usize = 0xFFFF - 2;
+/// Max data length of a single PNG chunk
+const PNG_CHUNK_SIZE: usize = i32::MAX as usize;
+
+/// Cover with the sealed payload stored where `mode` selects, failing on
+/// covers or modes the format doesn't support
+pub type Embed = fn(&[u8], Mode, &[u8]) -> Result<Vec<u8>>;
+
+fn insert(buffer: &[u8], pos: usize, payload: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(buffer.len() + payload.len());
+    output.extend_from_slice(&buffer[..pos]);
+    output.extend_from_slice(payload);
+    output.extend_from_slice(&buffer[pos..]);
+    output
+}
+
+/// APP15/COM segments right after the leading APPn/COM ones, or after EOI
+pub fn embed_jpeg(buffer: &[u8], mode: Mode, sealed: &[u8]) -> Result<Vec<u8>> {
+    let (segments, end) = utils::jpeg::segments(buffer)?;
+
+    let (pos, payload) = match mode {
+        Mode::Trailing => (end, sealed.to_vec()),
+        mode => {
+            let marker = utils::jpeg::container_marker(mode);
+            let pos = segments
+                .iter()
+                .find(|segment| !matches!(segment.marker, 0xE0..=0xEF | 0xFE))
+                .map_or(end, |segment| segment.start);
+            let mut payload = Vec::new();
+            for chunk in sealed.chunks(JPEG_SEGMENT_SIZE) {
+                payload.extend_from_slice(&[0xFF, marker]);
+                payload.extend_from_slice(&(chunk.len() as u16 + 2).to_be_bytes());
+                payload.extend_from_slice(chunk);
+            }
+            (pos, payload)
+        }
+    };
+
+    Ok(insert(buffer, pos, &payload))
+}
+
+/// Private ancillary chunks right before IEND, or after IEND
+pub fn embed_png(buffer: &[u8], mode: Mode, sealed: &[u8]) -> Result<Vec<u8>> {
+    let (chunks, end) = utils::png::split_chunks(buffer)?;
+
+    let (pos, payload) = match mode {
+        Mode::Trailing => (end, sealed.to_vec()),
+        Mode::Comment => bail!("comment mode isn't supported for PNG"),
+        _ => {
+            let iend = chunks.last().map_or(0, |chunk| chunk.data.len() + 12);
+            let mut payload = Vec::new();
+            for chunk in sealed.chunks(PNG_CHUNK_SIZE) {
+                let chunk = utils::png::Chunk {
+                    kind: utils::png::PRIVATE_CHUNK,
+                    data: chunk.to_vec(),
+                };
+                utils::png::write_chunk(&mut payload, &chunk);
+            }
+            (end - iend, payload)
+        }
+    };
+
+    Ok(insert(buffer, pos, &payload))
+}
+
+/// Stores the payload envelope in the cover through `embed`, keeping the
+/// rest of the file byte-for-byte
+pub struct ContainerEncoder {
+    buffer: Vec<u8>,
+    data: Vec<u8>,
+    extra: ExtraArgs,
+    embed: Embed,
+}
+
+impl ContainerEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs, embed: Embed) -> Result<Self> {
+        ensure!(extra.mode != Mode::Lsb, "container requires a non-LSB mode");
+        // nothing stored, only checks the cover and the mode
+        embed(image_buffer, extra.mode, &[])?;
+        Ok(Self {
+            buffer: image_buffer.to_vec(),
+            data: Vec::new(),
+            extra,
+            embed,
+        })
+    }
+}
+
+impl Encoder for ContainerEncoder {
+    fn total_size(&self) -> usize {
+        (u32::MAX as usize) << 3
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn writer(&mut self, len: usize) -> Result<DataWriter<'_>> {
+        self.check_size(len)?;
+        Ok(DataWriter::from_bytes(&mut self.data, len))
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        ensure!(!self.data.is_empty(), "no data written");
+        let sealed = utils::envelope::seal(&self.data, self.extra.key.clone());
+        (self.embed)(&self.buffer, self.extra.mode, &sealed)
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::Result;
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::flac::Flac;
+use crate::utils::lsb::Lsb;
+
+use super::LsbEncoder;
+
+/// FLAC, embeds in decoded samples of the selected channels and re-encodes
+/// losslessly
+pub struct FlacEncoder {
+    flac: Flac,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl FlacEncoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let flac = Flac::decode(buffer)?;
+        let size = utils::pcm::check(&flac.pcm, &extra)?;
+        Ok(Self { flac, size, extra })
+    }
+}
+
+impl LsbEncoder for FlacEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::pcm::units_mut(&mut self.flac.pcm, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        Ok(self.flac.encode())
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::{ensure, Result};
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::gif::Animation;
+use crate::utils::lsb::Lsb;
+use crate::utils::palette::PaletteOrder;
+
+use super::LsbEncoder;
+
+/// GIF (animated too), embeds into parity of luminance-ordered palette ranks
+/// of every frame
+pub struct GifEncoder {
+    animation: Animation,
+    orders: Vec<PaletteOrder>,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl GifEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        ensure!(
+            extra.depth == 0 && extra.bits == 1,
+            "invalid depth and bits for palette image: {} + {}, only 0 + 1 is supported",
+            extra.depth,
+            extra.bits
+        );
+        let mut animation = Animation::decode(image_buffer)?;
+        let orders = animation.orders()?;
+        let mut size = 0;
+        for (frame, order) in animation.frames.iter_mut().zip(&orders) {
+            let mut ranks = frame.buffer.to_vec();
+            for index in ranks.iter_mut() {
+                *index = order.rank(*index)?;
+            }
+            size += ranks.iter().filter(|&&rank| order.is_usable(rank)).count();
+            frame.buffer = ranks.into();
+        }
+        ensure!(size > 32, "GIF is too small");
+        Ok(Self {
+            animation,
+            orders,
+            size,
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for GifEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        let ranks =
+            self.animation
+                .frames
+                .iter_mut()
+                .zip(&self.orders)
+                .flat_map(|(frame, order)| {
+                    frame
+                        .buffer
+                        .to_mut()
+                        .iter_mut()
+                        .filter(move |rank| order.is_usable(**rank))
+                });
+        Ok(utils::iter::units_mut(
+            ranks,
+            self.extra.key.clone(),
+            seek,
+            max_step,
+        ))
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        let mut animation = self.animation.clone();
+        for (frame, order) in animation.frames.iter_mut().zip(&self.orders) {
+            for rank in frame.buffer.to_mut().iter_mut() {
+                *rank = order.index(*rank);
+            }
+        }
+        animation.encode()
+    }
+}
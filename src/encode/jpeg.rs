@@ -1,24 +1,22 @@
-use anyhow::{bail, ensure, Result};
-use bitvec::slice::BitSlice;
+use anyhow::{ensure, Result};
+use image::DynamicImage;
 use mozjpeg_sys::{
-    jpeg_copy_critical_parameters, jpeg_decompress_struct, jpeg_destroy_compress,
-    jpeg_destroy_decompress, jpeg_finish_compress, jpeg_finish_decompress, jpeg_write_coefficients,
-    jvirt_barray_control,
+    jpeg_copy_critical_parameters, jpeg_decompress_struct, jpeg_finish_compress,
+    jpeg_finish_decompress, jpeg_write_coefficients, jvirt_barray_control,
 };
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
-use rand_seeder::Seeder;
 
-use crate::options::{ExtraArgs, ImageOptions};
+use crate::options::{ExtraArgs, ImageOptions, JpegOptions};
 use crate::utils;
+use crate::utils::lsb::Lsb;
 
-use super::Encoder;
+use super::LsbEncoder;
 
 pub struct JpegEncoder {
     cinfo: jpeg_decompress_struct,
     coefs_ptr: *mut *mut jvirt_barray_control,
     total_size: usize,
     blocks: utils::jpeg::Blocks,
+    source: utils::jpeg::SourceParams,
     extra: ExtraArgs,
 }
 
@@ -31,6 +29,7 @@ impl JpegEncoder {
             extra.bits
         );
 
+        let source = utils::jpeg::source_params(image_buffer)?;
         let (cinfo, coefs_ptr, total_size, blocks) =
             unsafe { utils::jpeg::decompress(image_buffer, &extra)? };
 
@@ -45,12 +44,24 @@ impl JpegEncoder {
             coefs_ptr,
             total_size,
             blocks,
+            source,
             extra,
         })
     }
+
+    /// Compress pixels with mozjpeg first, then embed into the freshly
+    /// quantized DCT coefs
+    pub fn from_image(
+        image: &DynamicImage,
+        jpeg_options: &JpegOptions,
+        extra: ExtraArgs,
+    ) -> Result<Self> {
+        let buffer = unsafe { utils::jpeg::compress_pixels(image, jpeg_options)? };
+        Self::new(&buffer, extra)
+    }
 }
 
-impl Encoder for JpegEncoder {
+impl LsbEncoder for JpegEncoder {
     fn total_size(&self) -> usize {
         (self.total_size - 32) * self.extra().bits
     }
@@ -59,29 +70,18 @@ impl Encoder for JpegEncoder {
         self.extra.clone()
     }
 
-    fn write(&mut self, data: &BitSlice<u8>, seek: usize, max_step: usize) -> Result<()> {
-        let mut image_iter = self.blocks.iter_mut(self.extra());
-
-        let mut rng = ChaCha20Rng::from_seed(Seeder::from(self.extra().key).make_seed());
-        let mut data_iter = data.iter();
-        let mask = (u16::MAX << self.extra().bits).rotate_left(self.extra().depth as u32) as i16;
-
-        if seek > 0 {
-            image_iter.nth(seek - 1);
-        }
-
-        while let Some(coef) = image_iter.nth(utils::iter::rand_step(&mut rng, max_step)) {
-            let bits: i16 = match utils::iter::get_n_bits(&mut data_iter, self.extra().bits) {
-                Ok(bits) => bits,
-                Err(_) => return Ok(()),
-            };
-            *coef = (*coef & mask) | (bits << self.extra().depth);
-        }
-
-        if data_iter.next().is_some() {
-            bail!("image ended but data not");
-        }
-        Ok(())
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        let image_iter = self.blocks.iter_mut(self.extra());
+        Ok(utils::iter::units_mut(
+            image_iter,
+            self.extra.key.clone(),
+            seek,
+            max_step,
+        ))
     }
 
     fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>> {
@@ -90,13 +90,19 @@ impl Encoder for JpegEncoder {
             let buffer_size: *mut libc::c_ulong = &mut 0;
             let mut dstinfo = utils::jpeg::compress(buffer_ptr, buffer_size);
 
-            utils::jpeg::set_options(&mut dstinfo, image_opts.jpeg);
+            utils::jpeg::set_options(&mut dstinfo, &image_opts.jpeg);
             jpeg_copy_critical_parameters(&self.cinfo, &mut dstinfo);
+            if image_opts.jpeg.mimic_source {
+                utils::jpeg::mimic_source(&mut dstinfo, &self.source);
+            }
 
             jpeg_write_coefficients(&mut dstinfo, self.coefs_ptr);
+            if !image_opts.jpeg.strip {
+                utils::jpeg::copy_markers(&self.cinfo, &mut dstinfo);
+            }
 
             jpeg_finish_compress(&mut dstinfo);
-            jpeg_destroy_compress(&mut dstinfo);
+            utils::jpeg::destroy_compress(&mut dstinfo);
 
             Vec::from_raw_parts(*buffer_ptr, *buffer_size as usize, *buffer_size as usize)
         };
@@ -108,7 +114,7 @@ impl Drop for JpegEncoder {
     fn drop(&mut self) {
         unsafe {
             jpeg_finish_decompress(&mut self.cinfo);
-            jpeg_destroy_decompress(&mut self.cinfo);
+            utils::jpeg::destroy_decompress(&mut self.cinfo);
         }
     }
 }
@@ -1,29 +1,44 @@
+pub mod apng;
+pub mod container;
+pub mod flac;
+pub mod gif;
 pub mod jpeg;
 pub mod png;
+pub mod pnm;
+pub mod qoi;
+pub mod raster;
+pub mod text;
+pub mod tiff;
+pub mod wav;
+pub mod webp;
+mod writer;
+pub mod y4m;
 
+use std::io::Write;
 use std::path::PathBuf;
 
+use crate::format;
 use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
 use anyhow::{bail, ensure, Result};
 use bitvec::slice::BitSlice;
 use bitvec::view::BitView;
 
-use self::jpeg::JpegEncoder;
-use self::png::PngEncoder;
+pub use self::writer::DataWriter;
 
+/// Cover carrying a payload, written through `writer` then encoded back to
+/// its format
 pub trait Encoder {
-    fn write(&mut self, data: &BitSlice<u8>, seek: usize, max_step: usize) -> Result<()>;
+    fn writer(&mut self, len: usize) -> Result<DataWriter<'_>>;
     fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>>;
     fn total_size(&self) -> usize;
     fn extra(&self) -> ExtraArgs;
 
     fn write_data(&mut self, data: &[u8]) -> Result<()> {
-        self.check_size(data.len())?;
-
-        self.write((data.len() as u32).to_le_bytes().view_bits(), 0, 0)?;
-        self.write(data.view_bits(), 32, self.max_step(data.len())?)?;
-
-        Ok(())
+        let mut writer = self.writer(data.len())?;
+        writer.write_all(data)?;
+        writer.finish()
     }
 
     fn check_size(&self, data_size: usize) -> Result<()> {
@@ -36,6 +51,37 @@ pub trait Encoder {
         );
         Ok(())
     }
+}
+
+/// Cover embedding the payload bits into LSB units such as pixel channels,
+/// DCT coefs or samples, after a 32-bit size header
+pub trait LsbEncoder {
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>>;
+    fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>>;
+    fn total_size(&self) -> usize;
+    fn extra(&self) -> ExtraArgs;
+
+    fn write(&mut self, data: &BitSlice<u8>, seek: usize, max_step: usize) -> Result<()> {
+        let extra = self.extra();
+        let mut data_iter = data.iter();
+
+        for unit in self.units(seek, max_step)? {
+            let bits = match utils::iter::get_n_bits(&mut data_iter, extra.bits) {
+                Ok(bits) => bits,
+                Err(_) => return Ok(()),
+            };
+            unit.set_bits(extra.depth, extra.bits, bits);
+        }
+
+        if data_iter.next().is_some() {
+            bail!("image ended but data not");
+        }
+        Ok(())
+    }
 
     fn max_step(&self, data_size: usize) -> Result<usize> {
         let data_size = data_size << 3;
@@ -49,14 +95,66 @@ pub trait Encoder {
     }
 }
 
+impl<T: LsbEncoder> Encoder for T {
+    fn writer(&mut self, len: usize) -> Result<DataWriter<'_>> {
+        self.check_size(len)?;
+
+        self.write((len as u32).to_le_bytes().view_bits(), 0, 0)?;
+        let max_step = self.max_step(len)?;
+        let extra = LsbEncoder::extra(self);
+
+        Ok(DataWriter::new(self.units(32, max_step)?, len, &extra))
+    }
+
+    fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>> {
+        LsbEncoder::encode_image(self, image_opts)
+    }
+
+    fn total_size(&self) -> usize {
+        LsbEncoder::total_size(self)
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        LsbEncoder::extra(self)
+    }
+}
+
 pub fn new_encoder(input: PathBuf, extra_args: ExtraArgs) -> Result<Box<dyn Encoder>> {
-    let image_buf = std::fs::read(input)?;
-    match image::guess_format(&image_buf)? {
-        image::ImageFormat::Png => Ok(Box::new(PngEncoder::new(
-            image::load_from_memory(&image_buf)?,
-            extra_args,
-        )?)),
-        image::ImageFormat::Jpeg => Ok(Box::new(JpegEncoder::new(&image_buf, extra_args)?)),
-        _ => bail!("invalid image format"),
+    let buffer = std::fs::read(input)?;
+    let format = format::find_cover(&buffer, &extra_args)?;
+    (format.encoder)(&buffer, extra_args)
+}
+
+/// Encoder writing the `output` format, the cover one when `None`. The cover
+/// is converted through its pixels when stored in another format, using
+/// `image_opts` for the pixel compression of JPEG
+pub fn new_encoder_to(
+    input: PathBuf,
+    output: Option<&str>,
+    extra_args: ExtraArgs,
+    image_opts: &ImageOptions,
+) -> Result<Box<dyn Encoder>> {
+    let buffer = std::fs::read(input)?;
+    let input = format::find_cover(&buffer, &extra_args)?;
+    let output = match output {
+        Some(output) => format::by_name(output)?,
+        None => input,
+    };
+    // JPEG covers go through pixels to be quantized again
+    let requantize = image_opts.jpeg.requantize;
+    ensure!(
+        !requantize || output.name == "jpeg",
+        "requantizing only applies to JPEG output, not {}",
+        output.name
+    );
+    ensure!(
+        output.name == "jpeg" && (input.name != "jpeg" || requantize)
+            || !image_opts.jpeg.has_pixel_options(),
+        "JPEG quality, quantization, subsampling and trellis options only apply when \
+         compressing pixels: a non-JPEG cover or a requantized JPEG one to JPEG output"
+    );
+    if input.name == output.name && !requantize {
+        return (input.encoder)(&buffer, extra_args);
     }
+    format::convert(&buffer, &input, &output, extra_args, image_opts)
 }
@@ -1,74 +1,54 @@
 use crate::options::{ExtraArgs, ImageOptions};
 use crate::utils;
-use anyhow::{bail, ensure, Result};
-use bitvec::prelude::*;
+use crate::utils::lsb::Lsb;
+use crate::utils::palette::PaletteOrder;
+use crate::utils::png::{Chunk, IndexedImage};
+use anyhow::{ensure, Result};
 use image::{DynamicImage, ImageEncoder};
-use rand::SeedableRng;
-use rand_chacha::ChaCha20Rng;
-use rand_seeder::Seeder;
 
-use super::Encoder;
+use super::LsbEncoder;
 
 pub struct PngEncoder {
     pub image: DynamicImage,
+    size: usize,
+    chunks: Vec<Chunk>,
     extra: ExtraArgs,
 }
 
 impl PngEncoder {
     pub fn new(image: DynamicImage, extra: ExtraArgs) -> Result<Self> {
-        ensure!(
-            extra.depth + extra.bits <= 8,
-            "invalid depth and bits: {} + {} > 8",
-            extra.depth,
-            extra.bits
-        );
-        Ok(Self { image, extra })
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self {
+            image,
+            size,
+            chunks: Vec::new(),
+            extra,
+        })
+    }
+
+    /// Load PNG file keeping its ancillary chunks for output
+    pub fn from_buffer(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let mut encoder = Self::new(image::load_from_memory(image_buffer)?, extra)?;
+        encoder.chunks = utils::png::read_chunks(image_buffer)?;
+        Ok(encoder)
     }
 }
 
-impl Encoder for PngEncoder {
+impl LsbEncoder for PngEncoder {
     fn total_size(&self) -> usize {
-        (self.image.width() as usize
-            * self.image.height() as usize
-            * self.image.color().channel_count() as usize
-            - 32)
-            * self.extra().bits
+        (self.size - 32) * self.extra().bits
     }
 
     fn extra(&self) -> ExtraArgs {
         self.extra.clone()
     }
 
-    fn write(&mut self, data: &BitSlice<u8>, seek: usize, max_step: usize) -> Result<()> {
-        let mut image_iter = match &mut self.image {
-            DynamicImage::ImageRgb8(img_buf) => img_buf.iter_mut(),
-            DynamicImage::ImageRgba8(img_buf) => img_buf.iter_mut(),
-            _ => bail!("invalid color format"),
-        };
-
-        let mut rng = ChaCha20Rng::from_seed(Seeder::from(self.extra.key.clone()).make_seed());
-        let mut data_iter = data.iter();
-        let mask = u8::MAX
-            .checked_shl(self.extra.bits as u32)
-            .unwrap_or(0)
-            .rotate_left(self.extra.depth as u32);
-
-        if seek > 0 {
-            image_iter.nth(seek - 1);
-        }
-
-        while let Some(pixel) = image_iter.nth(utils::iter::rand_step(&mut rng, max_step)) {
-            let bits: u8 = match utils::iter::get_n_bits(&mut data_iter, self.extra.bits) {
-                Ok(bits) => bits,
-                Err(_) => return Ok(()),
-            };
-            *pixel = (*pixel & mask) | (bits << self.extra.depth);
-        }
-
-        if data_iter.next().is_some() {
-            bail!("image ended but data not");
-        }
-        Ok(())
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::units_mut(&mut self.image, &self.extra, seek, max_step)
     }
 
     fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>> {
@@ -84,6 +64,75 @@ impl Encoder for PngEncoder {
             self.image.height(),
             self.image.color().into(),
         )?;
-        Ok(buffer)
+        if image_opts.png.strip {
+            return Ok(buffer);
+        }
+        utils::png::splice(&self.chunks, &buffer)
+    }
+}
+
+/// Indexed-color PNG, embeds into parity of luminance-ordered palette ranks
+pub struct PngPaletteEncoder {
+    image: IndexedImage,
+    order: PaletteOrder,
+    chunks: Vec<Chunk>,
+    extra: ExtraArgs,
+}
+
+impl PngPaletteEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        ensure!(
+            extra.depth == 0 && extra.bits == 1,
+            "invalid depth and bits for palette image: {} + {}, only 0 + 1 is supported",
+            extra.depth,
+            extra.bits
+        );
+        let mut image = IndexedImage::decode(image_buffer)?;
+        ensure!(image.indices.len() > 32, "image is too small");
+        let order = PaletteOrder::new(&image.palette, image.trns.as_deref())?;
+        for index in image.indices.iter_mut() {
+            *index = order.rank(*index)?;
+        }
+        Ok(Self {
+            image,
+            order,
+            chunks: utils::png::read_chunks(image_buffer)?,
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for PngPaletteEncoder {
+    fn total_size(&self) -> usize {
+        (self.image.indices.len() - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        Ok(utils::iter::units_mut(
+            self.image.indices.iter_mut(),
+            self.extra.key.clone(),
+            seek,
+            max_step,
+        ))
+    }
+
+    fn encode_image(&self, image_opts: ImageOptions) -> Result<Vec<u8>> {
+        let mut image = self.image.clone();
+        for rank in image.indices.iter_mut() {
+            *rank = self.order.index(*rank);
+        }
+        let buffer = image.encode(&image_opts.png)?;
+        if image_opts.png.strip {
+            return Ok(buffer);
+        }
+        utils::png::splice(&self.chunks, &buffer)
     }
 }
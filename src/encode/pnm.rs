@@ -0,0 +1,53 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat};
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::pnm::Header;
+
+use super::LsbEncoder;
+
+/// Netpbm PGM/PPM/PAM, written back with the original header
+pub struct PnmEncoder {
+    pub image: DynamicImage,
+    size: usize,
+    header: Header,
+    extra: ExtraArgs,
+}
+
+impl PnmEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let header = Header::read(image_buffer)?;
+        let image = image::load_from_memory_with_format(image_buffer, ImageFormat::Pnm)?;
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self {
+            image,
+            size,
+            header,
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for PnmEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::units_mut(&mut self.image, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        Ok(self.header.encode(&self.image))
+    }
+}
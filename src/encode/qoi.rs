@@ -0,0 +1,63 @@
+use anyhow::{ensure, Result};
+use image::{DynamicImage, ImageEncoder, ImageFormat};
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+use super::LsbEncoder;
+
+/// Offset of the colorspace byte in the QOI header
+const COLORSPACE_OFFSET: usize = 13;
+
+/// QOI, written back keeping the colorspace of the cover
+pub struct QoiEncoder {
+    pub image: DynamicImage,
+    size: usize,
+    colorspace: u8,
+    extra: ExtraArgs,
+}
+
+impl QoiEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        ensure!(image_buffer.len() > COLORSPACE_OFFSET, "invalid QOI header");
+        let image = image::load_from_memory_with_format(image_buffer, ImageFormat::Qoi)?;
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self {
+            image,
+            size,
+            colorspace: image_buffer[COLORSPACE_OFFSET],
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for QoiEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::units_mut(&mut self.image, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        image::codecs::qoi::QoiEncoder::new(&mut buffer).write_image(
+            self.image.as_bytes(),
+            self.image.width(),
+            self.image.height(),
+            self.image.color().into(),
+        )?;
+        buffer[COLORSPACE_OFFSET] = self.colorspace;
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{ColorType, DynamicImage, ImageFormat};
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+use super::LsbEncoder;
+
+/// Raw pixels written to a lossless format by the image crate, used for BMP
+/// covers and when the output format differs from the cover one
+pub struct RasterEncoder {
+    pub image: DynamicImage,
+    size: usize,
+    format: ImageFormat,
+    /// Original BMP file, kept when its pixels can be rewritten in place
+    original: Option<Vec<u8>>,
+    extra: ExtraArgs,
+}
+
+impl RasterEncoder {
+    pub fn new(image: DynamicImage, format: ImageFormat, extra: ExtraArgs) -> Result<Self> {
+        let image = supported_color(image, format);
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self {
+            image,
+            size,
+            format,
+            original: None,
+            extra,
+        })
+    }
+
+    pub fn from_bmp(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let (info, image) = utils::bmp::load(image_buffer)?;
+        let mut encoder = Self::new(image, ImageFormat::Bmp, extra)?;
+        if info.is_plain_rgb() && matches!(encoder.image, DynamicImage::ImageRgb8(_)) {
+            encoder.original = Some(image_buffer.to_vec());
+        }
+        Ok(encoder)
+    }
+}
+
+/// Convert pixels before embedding to a color type the format writes as is,
+/// PNM is written as PGM/PPM which can't store alpha
+fn supported_color(image: DynamicImage, format: ImageFormat) -> DynamicImage {
+    match (format, image.color()) {
+        (ImageFormat::Bmp | ImageFormat::WebP | ImageFormat::Qoi, color) if color.has_alpha() => {
+            image.into_rgba8().into()
+        }
+        (ImageFormat::Bmp | ImageFormat::WebP | ImageFormat::Qoi, _) => image.into_rgb8().into(),
+        (ImageFormat::Pnm, ColorType::La8) => image.into_luma8().into(),
+        (ImageFormat::Pnm, ColorType::La16) => image.into_luma16().into(),
+        (ImageFormat::Pnm, ColorType::Rgba8) => image.into_rgb8().into(),
+        (ImageFormat::Pnm, ColorType::Rgba16) => image.into_rgb16().into(),
+        _ => image,
+    }
+}
+
+impl LsbEncoder for RasterEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::units_mut(&mut self.image, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        if let (Some(original), DynamicImage::ImageRgb8(image)) = (&self.original, &self.image) {
+            return utils::bmp::patch(original, image);
+        }
+        let mut buffer = Vec::new();
+        self.image
+            .write_to(&mut Cursor::new(&mut buffer), self.format)?;
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,53 @@
+use anyhow::{ensure, Result};
+
+use crate::options::{ExtraArgs, ImageOptions, Mode};
+use crate::utils;
+
+use super::{DataWriter, Encoder};
+
+/// Plain text, stores the payload envelope as zero-width characters between
+/// words or as trailing spaces and tabs at line ends
+pub struct TextEncoder {
+    text: String,
+    data: Vec<u8>,
+    extra: ExtraArgs,
+}
+
+impl TextEncoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        ensure!(
+            matches!(extra.mode, Mode::Lsb | Mode::ZeroWidth | Mode::Whitespace),
+            "only zero-width and whitespace modes are supported for text"
+        );
+        Ok(Self {
+            text: String::from_utf8(buffer.to_vec())?,
+            data: Vec::new(),
+            extra,
+        })
+    }
+}
+
+impl Encoder for TextEncoder {
+    fn total_size(&self) -> usize {
+        (u32::MAX as usize) << 3
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn writer(&mut self, len: usize) -> Result<DataWriter<'_>> {
+        self.check_size(len)?;
+        Ok(DataWriter::from_bytes(&mut self.data, len))
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        ensure!(!self.data.is_empty(), "no data written");
+        let sealed = utils::envelope::seal(&self.data, self.extra.key.clone());
+        let text = match self.extra.mode {
+            Mode::Whitespace => utils::text::embed_whitespace(&self.text, &sealed)?,
+            _ => utils::text::embed_zero_width(&self.text, &sealed)?,
+        };
+        Ok(text.into_bytes())
+    }
+}
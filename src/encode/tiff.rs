@@ -0,0 +1,54 @@
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::tiff::Pages;
+
+use super::LsbEncoder;
+
+/// Multi-page TIFF, spreads data over pixels of all pages
+pub struct TiffEncoder {
+    pages: Pages,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl TiffEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        Self::from_pages(Pages::decode(image_buffer)?, extra)
+    }
+
+    /// Single page TIFF of raw pixels
+    pub fn from_image(image: DynamicImage, extra: ExtraArgs) -> Result<Self> {
+        Self::from_pages(Pages::from_image(image), extra)
+    }
+
+    fn from_pages(pages: Pages, extra: ExtraArgs) -> Result<Self> {
+        let size = utils::raster::frames_check(&pages.frames, &extra)?;
+        Ok(Self { pages, size, extra })
+    }
+}
+
+impl LsbEncoder for TiffEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::frames_units_mut(&mut self.pages.frames, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        self.pages.encode()
+    }
+}
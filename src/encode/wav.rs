@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::wav::Wav;
+
+use super::LsbEncoder;
+
+/// 16-bit and 24-bit PCM WAV, embeds in samples of the selected channels
+pub struct WavEncoder {
+    wav: Wav,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl WavEncoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let wav = Wav::decode(buffer)?;
+        let size = utils::pcm::check(&wav.pcm, &extra)?;
+        Ok(Self { wav, size, extra })
+    }
+}
+
+impl LsbEncoder for WavEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::pcm::units_mut(&mut self.wav.pcm, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        Ok(self.wav.encode())
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageEncoder};
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::webp::Chunk;
+
+use super::LsbEncoder;
+
+/// Lossless WebP, written back as VP8L keeping metadata chunks
+pub struct WebpEncoder {
+    pub image: DynamicImage,
+    size: usize,
+    chunks: Vec<Chunk>,
+    extra: ExtraArgs,
+}
+
+impl WebpEncoder {
+    pub fn new(image_buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let chunks = utils::webp::read_chunks(image_buffer)?;
+        utils::webp::check_lossless(&chunks)?;
+        let image = image::load_from_memory_with_format(image_buffer, image::ImageFormat::WebP)?;
+        let size = utils::raster::check(&image, &extra)?;
+        Ok(Self {
+            image,
+            size,
+            chunks,
+            extra,
+        })
+    }
+}
+
+impl LsbEncoder for WebpEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::raster::units_mut(&mut self.image, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        image::codecs::webp::WebPEncoder::new_lossless(&mut buffer).write_image(
+            self.image.as_bytes(),
+            self.image.width(),
+            self.image.height(),
+            self.image.color().into(),
+        )?;
+        utils::webp::splice(&self.chunks, &buffer)
+    }
+}
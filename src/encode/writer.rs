@@ -0,0 +1,143 @@
+use std::io::{self, Write};
+
+use anyhow::{ensure, Result};
+
+use crate::options::ExtraArgs;
+use crate::utils::lsb::Lsb;
+
+/// Streaming payload writer, embeds bits into image units as data arrives
+pub struct DataWriter<'a> {
+    sink: Sink<'a>,
+    remaining: usize,
+}
+
+enum Sink<'a> {
+    Units {
+        units: Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>,
+        depth: usize,
+        bits: usize,
+        value: u16,
+        filled: usize,
+    },
+    Bytes(&'a mut Vec<u8>),
+}
+
+impl<'a> DataWriter<'a> {
+    pub(crate) fn new(
+        units: Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>,
+        size: usize,
+        extra: &ExtraArgs,
+    ) -> Self {
+        Self {
+            sink: Sink::Units {
+                units,
+                depth: extra.depth,
+                bits: extra.bits,
+                value: 0,
+                filled: 0,
+            },
+            remaining: size,
+        }
+    }
+
+    /// Writer collecting raw payload bytes, for containers storing data as is
+    pub(crate) fn from_bytes(buffer: &'a mut Vec<u8>, size: usize) -> Self {
+        buffer.clear();
+        buffer.reserve(size);
+        Self {
+            sink: Sink::Bytes(buffer),
+            remaining: size,
+        }
+    }
+
+    /// Number of payload bytes not written yet
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Embed trailing bits, must be called after all data is written
+    pub fn finish(mut self) -> Result<()> {
+        ensure!(
+            self.remaining == 0,
+            "data ended early: {} bytes missing",
+            self.remaining
+        );
+        self.sink.flush_unit()?;
+        Ok(())
+    }
+}
+
+impl Sink<'_> {
+    fn flush_unit(&mut self) -> io::Result<()> {
+        let Sink::Units {
+            units,
+            depth,
+            bits,
+            value,
+            filled,
+        } = self
+        else {
+            return Ok(());
+        };
+        if *filled == 0 {
+            return Ok(());
+        }
+        let unit = units
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "image ended but data not"))?;
+        unit.set_bits(*depth, *bits, *value << (*bits - *filled));
+        *value = 0;
+        *filled = 0;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        match self {
+            Sink::Units { .. } => {
+                for i in 0..8 {
+                    self.write_bit((byte >> i) & 1 == 1)?;
+                }
+            }
+            Sink::Bytes(buffer) => buffer.push(byte),
+        }
+        Ok(())
+    }
+
+    fn write_bit(&mut self, bit: bool) -> io::Result<()> {
+        if let Sink::Units {
+            bits,
+            value,
+            filled,
+            ..
+        } = self
+        {
+            *value = (*value << 1) | bit as u16;
+            *filled += 1;
+            if *filled == *bits {
+                self.flush_unit()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for DataWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() && self.remaining == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more data than declared",
+            ));
+        }
+        let len = buf.len().min(self.remaining);
+        for &byte in &buf[..len] {
+            self.sink.write_byte(byte)?;
+            self.remaining -= 1;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use crate::options::{ExtraArgs, ImageOptions};
+use crate::utils;
+use crate::utils::lsb::Lsb;
+use crate::utils::y4m::Video;
+
+use super::LsbEncoder;
+
+/// YUV4MPEG2 video, embeds in samples of the selected planes of all frames
+pub struct Y4mEncoder {
+    video: Video,
+    size: usize,
+    extra: ExtraArgs,
+}
+
+impl Y4mEncoder {
+    pub fn new(buffer: &[u8], extra: ExtraArgs) -> Result<Self> {
+        let video = Video::decode(buffer)?;
+        let size = utils::y4m::check(&video, &extra)?;
+        Ok(Self { video, size, extra })
+    }
+}
+
+impl LsbEncoder for Y4mEncoder {
+    fn total_size(&self) -> usize {
+        (self.size - 32) * self.extra().bits
+    }
+
+    fn extra(&self) -> ExtraArgs {
+        self.extra.clone()
+    }
+
+    fn units(
+        &mut self,
+        seek: usize,
+        max_step: usize,
+    ) -> Result<Box<dyn Iterator<Item = &mut dyn Lsb> + '_>> {
+        utils::y4m::units_mut(&mut self.video, &self.extra, seek, max_step)
+    }
+
+    fn encode_image(&self, _image_opts: ImageOptions) -> Result<Vec<u8>> {
+        Ok(self.video.encode())
+    }
+}
@@ -0,0 +1,453 @@
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::{bail, ensure, Context, Result};
+use image::{DynamicImage, ImageFormat};
+
+use crate::decode::apng::ApngDecoder;
+use crate::decode::container::{
+    extract_jpeg, extract_png, ContainerDecoder, JPEG_MODES, PNG_MODES,
+};
+use crate::decode::flac::FlacDecoder;
+use crate::decode::gif::GifDecoder;
+use crate::decode::jpeg::JpegDecoder;
+use crate::decode::png::{PngDecoder, PngPaletteDecoder};
+use crate::decode::raster::RasterDecoder;
+use crate::decode::text::TextDecoder;
+use crate::decode::tiff::TiffDecoder;
+use crate::decode::wav::WavDecoder;
+use crate::decode::y4m::Y4mDecoder;
+use crate::decode::Decoder;
+use crate::encode::apng::ApngEncoder;
+use crate::encode::container::{embed_jpeg, embed_png, ContainerEncoder};
+use crate::encode::flac::FlacEncoder;
+use crate::encode::gif::GifEncoder;
+use crate::encode::jpeg::JpegEncoder;
+use crate::encode::png::{PngEncoder, PngPaletteEncoder};
+use crate::encode::pnm::PnmEncoder;
+use crate::encode::qoi::QoiEncoder;
+use crate::encode::raster::RasterEncoder;
+use crate::encode::text::TextEncoder;
+use crate::encode::tiff::TiffEncoder;
+use crate::encode::wav::WavEncoder;
+use crate::encode::webp::WebpEncoder;
+use crate::encode::y4m::Y4mEncoder;
+use crate::encode::Encoder;
+use crate::options::{ExtraArgs, ImageOptions, Mode};
+use crate::utils;
+
+pub type NewEncoder = fn(&[u8], ExtraArgs) -> Result<Box<dyn Encoder>>;
+pub type NewDecoder = fn(&[u8], ExtraArgs) -> Result<Box<dyn Decoder>>;
+pub type FromPixels = fn(DynamicImage, ExtraArgs, &ImageOptions) -> Result<Box<dyn Encoder>>;
+pub type MultiFrame = fn(&[u8]) -> Result<bool>;
+
+/// Cover format backend: content detection and encoder/decoder constructors
+#[derive(Clone, Copy)]
+pub struct Format {
+    /// Name used by the `--format` override
+    pub name: &'static str,
+    /// Output file extensions, lowercase
+    pub extensions: &'static [&'static str],
+    /// Whether the buffer starts with this format's magic bytes
+    pub detect: fn(&[u8]) -> bool,
+    pub encoder: NewEncoder,
+    pub decoder: NewDecoder,
+    /// Encoder writing pixels of a cover in another format, `None` when the
+    /// payload wouldn't survive the format's compression
+    pub from_pixels: Option<FromPixels>,
+    /// Whether a cover holds several frames or pages, only the first one
+    /// survives a conversion. `None` for single image formats
+    pub multi_frame: Option<MultiFrame>,
+}
+
+static REGISTERED: RwLock<Vec<Format>> = RwLock::new(Vec::new());
+
+/// Register a format backend, it is detected before the built-in ones and
+/// replaces any format of the same name
+pub fn register(format: Format) {
+    let mut registered = REGISTERED.write().unwrap_or_else(|err| err.into_inner());
+    registered.retain(|other| other.name != format.name);
+    registered.push(format);
+}
+
+/// Registered formats, latest first, followed by the built-in ones
+pub fn formats() -> Vec<Format> {
+    let registered = REGISTERED.read().unwrap_or_else(|err| err.into_inner());
+    let mut formats: Vec<Format> = registered.iter().rev().copied().collect();
+    formats.extend(
+        builtin()
+            .into_iter()
+            .filter(|format| registered.iter().all(|other| other.name != format.name)),
+    );
+    formats
+}
+
+/// Format named by `name`
+pub fn by_name(name: &str) -> Result<Format> {
+    let formats = formats();
+    match formats.iter().find(|format| format.name == name) {
+        Some(format) => Ok(*format),
+        None => bail!(
+            "unknown format {name}, expected one of: {}",
+            names(&formats)
+        ),
+    }
+}
+
+/// Format named by `name`, or detected from the content of `buffer`
+pub fn find(buffer: &[u8], name: Option<&str>) -> Result<Format> {
+    match name {
+        Some(name) => by_name(name),
+        None => match formats().into_iter().find(|format| (format.detect)(buffer)) {
+            Some(format) => Ok(format),
+            None => bail!("invalid image format"),
+        },
+    }
+}
+
+/// Format of a cover as given by `--format`, text for the text modes as plain
+/// text has no magic bytes to be detected by
+pub fn find_cover(buffer: &[u8], extra: &ExtraArgs) -> Result<Format> {
+    let name = match extra.mode {
+        Mode::ZeroWidth | Mode::Whitespace if extra.format.is_none() => Some("text"),
+        _ => extra.format.as_deref(),
+    };
+    find(buffer, name)
+}
+
+/// Format matching the extension of `path`
+pub fn from_extension(path: &Path) -> Option<Format> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    formats()
+        .into_iter()
+        .find(|format| format.extensions.contains(&extension.as_str()))
+}
+
+/// Encoder writing the pixels of a cover in `input` format to `output`,
+/// which has to keep the payload: lossless, or JPEG embedding after
+/// quantization
+pub fn convert(
+    buffer: &[u8],
+    input: &Format,
+    output: &Format,
+    extra: ExtraArgs,
+    image_opts: &ImageOptions,
+) -> Result<Box<dyn Encoder>> {
+    let Some(from_pixels) = output.from_pixels else {
+        let supported: Vec<Format> = formats()
+            .into_iter()
+            .filter(|format| format.from_pixels.is_some())
+            .collect();
+        // image formats without an encoder from pixels are the lossy ones
+        let raster = output
+            .extensions
+            .iter()
+            .any(|extension| ImageFormat::from_extension(extension).is_some());
+        if raster {
+            bail!(
+                "converting {} to {} is lossy and would destroy the payload, supported outputs: {}",
+                input.name,
+                output.name,
+                names(&supported)
+            );
+        }
+        bail!(
+            "{} can't be produced from pixels, supported outputs: {}",
+            output.name,
+            names(&supported)
+        );
+    };
+    ensure!(
+        extra.mode == Mode::Lsb,
+        "container modes can't be used when converting {} to {}",
+        input.name,
+        output.name
+    );
+    if let Some(multi_frame) = input.multi_frame {
+        ensure!(
+            !multi_frame(buffer)?,
+            "converting {} to {} would keep only the first frame (page) of the cover",
+            input.name,
+            output.name
+        );
+    }
+    let image = image::load_from_memory(buffer)
+        .with_context(|| format!("can't convert {} to {}", input.name, output.name))?;
+    from_pixels(image, extra, image_opts)
+}
+
+fn names(formats: &[Format]) -> String {
+    formats
+        .iter()
+        .map(|format| format.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Text modes only work with text, container modes with JPEG and PNG
+fn check_mode(mode: Mode, containers: bool) -> Result<()> {
+    match mode {
+        Mode::ZeroWidth | Mode::Whitespace => {
+            bail!("zero-width and whitespace modes are only supported for text")
+        }
+        Mode::Metadata | Mode::Comment | Mode::Trailing if !containers => {
+            bail!("container modes are only supported for JPEG and PNG")
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_image(buffer: &[u8], format: ImageFormat) -> bool {
+    image::guess_format(buffer).is_ok_and(|guess| guess == format)
+}
+
+fn raster(buffer: &[u8], format: ImageFormat) -> Result<DynamicImage> {
+    Ok(image::load_from_memory_with_format(buffer, format)?)
+}
+
+fn builtin() -> Vec<Format> {
+    vec![
+        Format {
+            name: "wav",
+            extensions: &["wav"],
+            detect: utils::wav::is_wav,
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(WavEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(WavDecoder::new(buffer, extra)?))
+            },
+            from_pixels: None,
+            multi_frame: None,
+        },
+        Format {
+            name: "flac",
+            extensions: &["flac"],
+            detect: utils::flac::is_flac,
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(FlacEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(FlacDecoder::new(buffer, extra)?))
+            },
+            from_pixels: None,
+            multi_frame: None,
+        },
+        Format {
+            name: "y4m",
+            extensions: &["y4m"],
+            detect: utils::y4m::is_y4m,
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(Y4mEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(Y4mDecoder::new(buffer, extra)?))
+            },
+            from_pixels: None,
+            multi_frame: None,
+        },
+        Format {
+            name: "jpeg",
+            extensions: &["jpg", "jpeg", "jfif"],
+            detect: |buffer| is_image(buffer, ImageFormat::Jpeg),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, true)?;
+                match extra.mode {
+                    Mode::Lsb => Ok(Box::new(JpegEncoder::new(buffer, extra)?)),
+                    _ => Ok(Box::new(ContainerEncoder::new(buffer, extra, embed_jpeg)?)),
+                }
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, true)?;
+                match ContainerDecoder::detect(buffer, &extra, JPEG_MODES, extract_jpeg)? {
+                    Some(decoder) => Ok(Box::new(decoder)),
+                    None => Ok(Box::new(JpegDecoder::new(buffer, extra)?)),
+                }
+            },
+            from_pixels: Some(|image, extra, image_opts| {
+                Ok(Box::new(JpegEncoder::from_image(
+                    &image,
+                    &image_opts.jpeg,
+                    extra,
+                )?))
+            }),
+            multi_frame: None,
+        },
+        Format {
+            name: "png",
+            extensions: &["png", "apng"],
+            detect: |buffer| is_image(buffer, ImageFormat::Png),
+            encoder: png_encoder,
+            decoder: png_decoder,
+            from_pixels: Some(|image, extra, _| Ok(Box::new(PngEncoder::new(image, extra)?))),
+            multi_frame: Some(utils::apng::is_animated),
+        },
+        Format {
+            name: "bmp",
+            extensions: &["bmp"],
+            detect: |buffer| is_image(buffer, ImageFormat::Bmp),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(RasterEncoder::from_bmp(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                let (_, image) = utils::bmp::load(buffer)?;
+                Ok(Box::new(RasterDecoder::new(image, extra)?))
+            },
+            from_pixels: Some(|image, extra, _| {
+                Ok(Box::new(RasterEncoder::new(
+                    image,
+                    ImageFormat::Bmp,
+                    extra,
+                )?))
+            }),
+            multi_frame: None,
+        },
+        Format {
+            name: "webp",
+            extensions: &["webp"],
+            detect: |buffer| is_image(buffer, ImageFormat::WebP),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(WebpEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                utils::webp::check_lossless(&utils::webp::read_chunks(buffer)?)?;
+                let image = raster(buffer, ImageFormat::WebP)?;
+                Ok(Box::new(RasterDecoder::new(image, extra)?))
+            },
+            from_pixels: Some(|image, extra, _| {
+                Ok(Box::new(RasterEncoder::new(
+                    image,
+                    ImageFormat::WebP,
+                    extra,
+                )?))
+            }),
+            multi_frame: Some(utils::webp::is_animated),
+        },
+        Format {
+            name: "gif",
+            extensions: &["gif"],
+            detect: |buffer| is_image(buffer, ImageFormat::Gif),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(GifEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(GifDecoder::new(buffer, extra)?))
+            },
+            from_pixels: None,
+            multi_frame: Some(utils::gif::is_animated),
+        },
+        Format {
+            name: "tiff",
+            extensions: &["tif", "tiff"],
+            detect: |buffer| is_image(buffer, ImageFormat::Tiff),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(TiffEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(TiffDecoder::new(buffer, extra)?))
+            },
+            from_pixels: Some(|image, extra, _| {
+                Ok(Box::new(TiffEncoder::from_image(image, extra)?))
+            }),
+            multi_frame: Some(utils::tiff::is_multipage),
+        },
+        Format {
+            name: "pnm",
+            extensions: &["pgm", "ppm", "pam", "pnm"],
+            detect: |buffer| is_image(buffer, ImageFormat::Pnm),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(PnmEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                utils::pnm::Header::read(buffer)?;
+                let image = raster(buffer, ImageFormat::Pnm)?;
+                Ok(Box::new(RasterDecoder::new(image, extra)?))
+            },
+            from_pixels: Some(|image, extra, _| {
+                Ok(Box::new(RasterEncoder::new(
+                    image,
+                    ImageFormat::Pnm,
+                    extra,
+                )?))
+            }),
+            multi_frame: None,
+        },
+        Format {
+            name: "qoi",
+            extensions: &["qoi"],
+            detect: |buffer| is_image(buffer, ImageFormat::Qoi),
+            encoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                Ok(Box::new(QoiEncoder::new(buffer, extra)?))
+            },
+            decoder: |buffer, extra| {
+                check_mode(extra.mode, false)?;
+                let image = raster(buffer, ImageFormat::Qoi)?;
+                Ok(Box::new(RasterDecoder::new(image, extra)?))
+            },
+            from_pixels: Some(|image, extra, _| {
+                Ok(Box::new(RasterEncoder::new(
+                    image,
+                    ImageFormat::Qoi,
+                    extra,
+                )?))
+            }),
+            multi_frame: None,
+        },
+        Format {
+            name: "text",
+            extensions: &["txt"],
+            // only picked by name or by the text modes
+            detect: |_| false,
+            encoder: |buffer, extra| Ok(Box::new(TextEncoder::new(buffer, extra)?)),
+            decoder: |buffer, extra| Ok(Box::new(TextDecoder::new(buffer, extra)?)),
+            from_pixels: None,
+            multi_frame: None,
+        },
+    ]
+}
+
+fn png_encoder(buffer: &[u8], extra: ExtraArgs) -> Result<Box<dyn Encoder>> {
+    check_mode(extra.mode, true)?;
+    match extra.mode {
+        Mode::Lsb if utils::apng::is_animated(buffer)? => {
+            Ok(Box::new(ApngEncoder::new(buffer, extra)?))
+        }
+        Mode::Lsb if utils::png::is_indexed(buffer)? => {
+            Ok(Box::new(PngPaletteEncoder::new(buffer, extra)?))
+        }
+        Mode::Lsb => Ok(Box::new(PngEncoder::from_buffer(buffer, extra)?)),
+        _ => Ok(Box::new(ContainerEncoder::new(buffer, extra, embed_png)?)),
+    }
+}
+
+fn png_decoder(buffer: &[u8], extra: ExtraArgs) -> Result<Box<dyn Decoder>> {
+    check_mode(extra.mode, true)?;
+    match ContainerDecoder::detect(buffer, &extra, PNG_MODES, extract_png)? {
+        Some(decoder) => Ok(Box::new(decoder)),
+        None if utils::apng::is_animated(buffer)? => Ok(Box::new(ApngDecoder::new(buffer, extra)?)),
+        None if utils::png::is_indexed(buffer)? => {
+            Ok(Box::new(PngPaletteDecoder::new(buffer, extra)?))
+        }
+        None => Ok(Box::new(PngDecoder::new(
+            raster(buffer, ImageFormat::Png)?,
+            extra,
+        )?)),
+    }
+}
@@ -1,4 +1,7 @@
 pub mod decode;
 pub mod encode;
+pub mod format;
 pub mod options;
 mod utils;
+
+pub use utils::lsb::Lsb;
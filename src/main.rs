@@ -2,13 +2,14 @@
 mod cli;
 
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use clap::{CommandFactory, Parser};
 use cli::{print_completions, Cli, Command, DecodeArgs, EncodeArgs};
 use s739::decode::new_decoder;
-use s739::encode::new_encoder;
+use s739::encode::{new_encoder_to, Encoder};
+use s739::format;
 
 fn decode(args: DecodeArgs) -> Result<()> {
     let DecodeArgs {
@@ -18,31 +19,53 @@ fn decode(args: DecodeArgs) -> Result<()> {
     } = args;
 
     let decoder = new_decoder(input, extra_args.into())?;
-    let data = decoder.read_data()?;
+    let mut reader = decoder.reader()?;
 
-    match file {
-        Some(file) => std::fs::write(file, data),
-        None => std::io::stdout().write_all(&data),
-    }?;
+    let mut output: Box<dyn Write> = match file {
+        Some(file) => Box::new(BufWriter::new(File::create(file)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    std::io::copy(&mut reader, &mut output)?;
+    output.flush()?;
+
+    Ok(())
+}
 
+fn stream_data(encoder: &mut dyn Encoder, mut reader: impl Read, len: usize) -> Result<()> {
+    let mut writer = encoder.writer(len)?;
+    std::io::copy(&mut reader.by_ref().take(len as u64), &mut writer)?;
+    writer.finish()?;
+    ensure!(
+        reader.read(&mut [0])? == 0,
+        "data is longer than {len} bytes"
+    );
     Ok(())
 }
 
-fn read_data(data: cli::Data) -> Result<Vec<u8>> {
-    let mut buf = Vec::new();
+fn write_data(encoder: &mut dyn Encoder, data: cli::Data, size: Option<usize>) -> Result<()> {
     match (data.text, data.file, data.stdin) {
-        (Some(text), _, _) => {
-            buf = text.as_bytes().to_vec();
-        }
+        (Some(text), _, _) => encoder.write_data(text.as_bytes()),
         (_, Some(file), _) => {
-            let _ = BufReader::new(File::open(file)?).read_to_end(&mut buf)?;
-        }
-        (_, _, true) => {
-            let _ = std::io::stdin().read_to_end(&mut buf)?;
+            let mut file = File::open(file)?;
+            let metadata = file.metadata()?;
+            // FIFOs and other special files have no length to stream with
+            if metadata.is_file() {
+                return stream_data(encoder, BufReader::new(file), metadata.len() as usize);
+            }
+            let mut buf = Vec::new();
+            let _ = file.read_to_end(&mut buf)?;
+            encoder.write_data(&buf)
         }
+        (_, _, true) => match size {
+            Some(size) => stream_data(encoder, std::io::stdin().lock(), size),
+            None => {
+                let mut buf = Vec::new();
+                let _ = std::io::stdin().read_to_end(&mut buf)?;
+                encoder.write_data(&buf)
+            }
+        },
         _ => unreachable!(),
-    };
-    Ok(buf)
+    }
 }
 
 fn encode(args: EncodeArgs) -> Result<()> {
@@ -50,14 +73,17 @@ fn encode(args: EncodeArgs) -> Result<()> {
         input,
         output,
         data,
+        size,
         image_opts,
         extra_args,
     } = args;
 
-    let mut encoder = new_encoder(input, extra_args.into())?;
-    let data = read_data(data)?;
-    encoder.write_data(&data)?;
-    let buffer = encoder.encode_image(image_opts.into())?;
+    let image_opts = image_opts.into();
+    // output format follows the extension, the cover one when it's unknown
+    let format = format::from_extension(&output).map(|format| format.name);
+    let mut encoder = new_encoder_to(input, format, extra_args.into(), &image_opts)?;
+    write_data(encoder.as_mut(), data, size)?;
+    let buffer = encoder.encode_image(image_opts)?;
     std::fs::write(output, buffer)?;
 
     Ok(())
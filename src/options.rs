@@ -12,6 +12,7 @@ pub struct ImageOptions {
 pub struct PngOptions {
     pub compression: png::CompressionType,
     pub filter: png::FilterType,
+    pub strip: bool,
 }
 
 #[derive(Debug, Clone, Derivative)]
@@ -19,16 +20,82 @@ pub struct PngOptions {
 pub struct JpegOptions {
     #[derivative(Default(value = "JINT_COMPRESS_PROFILE_VALUE::JCP_MAX_COMPRESSION"))]
     pub compress_profile: JINT_COMPRESS_PROFILE_VALUE,
+    pub strip: bool,
+    pub mimic_source: bool,
+    /// Quality used when compressing pixels, 75 when `None` unless custom
+    /// tables are given
+    pub quality: Option<u8>,
+    /// Custom quantization tables in row-major order, luma then chroma,
+    /// scaled by the quality if one is set
+    pub quant_tables: Vec<[u16; 64]>,
+    /// Chroma subsampling, 4:2:0 when `None`
+    pub subsampling: Option<Subsampling>,
+    /// Trellis quantization of AC coefs, profile default when `None`
+    pub trellis: Option<bool>,
+    /// Trellis quantization of DC coefs, profile default when `None`
+    pub trellis_dc: Option<bool>,
+    pub trellis_loops: Option<u8>,
+    /// Recompress JPEG covers from their pixels with the quality and
+    /// quantization options
+    pub requantize: bool,
+}
+
+impl JpegOptions {
+    /// Any quality, quantization, subsampling or trellis option is set,
+    /// which only applies when compressing pixels
+    pub fn has_pixel_options(&self) -> bool {
+        self.quality.is_some()
+            || !self.quant_tables.is_empty()
+            || self.subsampling.is_some()
+            || self.trellis.is_some()
+            || self.trellis_dc.is_some()
+            || self.trellis_loops.is_some()
+    }
+}
+
+/// Chroma subsampling of JPEG compressed from pixels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Chroma halved horizontally and vertically
+    #[default]
+    S420,
+    /// Chroma halved horizontally
+    S422,
+    /// Full resolution chroma
+    S444,
+}
+
+/// Where the payload is stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Least significant bits of pixels/DCT coefs
+    #[default]
+    Lsb,
+    /// Dedicated metadata segments (JPEG APP15, PNG private chunk)
+    Metadata,
+    /// Comment segments (JPEG COM)
+    Comment,
+    /// Data appended after the end of the image (JPEG EOI, PNG IEND)
+    Trailing,
+    /// Zero-width characters after the spaces of a text
+    ZeroWidth,
+    /// Spaces and tabs at the end of the lines of a text
+    Whitespace,
 }
 
 #[derive(Clone, Debug, Derivative)]
 #[derivative(Default)]
 pub struct ExtraArgs {
     pub key: Option<String>,
+    pub mode: Mode,
     pub selective: bool,
     pub depth: usize,
     #[derivative(Default(value = "1"))]
     pub bits: usize,
     pub jpeg_comp: Option<u8>,
+    pub channels: Option<Vec<u8>>,
+    pub skip_transparent: bool,
     pub max_step: Option<usize>,
+    /// Cover format name, detected from content when `None`
+    pub format: Option<String>,
 }
@@ -0,0 +1,189 @@
+use anyhow::{bail, ensure, Result};
+use image::codecs::png::PngEncoder;
+use image::{DynamicImage, ImageBuffer, ImageEncoder};
+
+use crate::options::PngOptions;
+use crate::utils::png::{self, Chunk};
+
+/// Chunks which drive the animation, kept even when stripping metadata
+const CONTROL_CHUNKS: [&[u8; 4]; 3] = [b"acTL", b"fcTL", b"fdAT"];
+
+/// Animated PNG, every frame (and the default image when it isn't part of the
+/// animation) decoded to pixels, original chunks kept for output
+pub struct Animation {
+    chunks: Vec<Chunk>,
+    pub frames: Vec<DynamicImage>,
+}
+
+pub fn is_animated(buffer: &[u8]) -> Result<bool> {
+    Ok(png::read_chunks(buffer)?
+        .iter()
+        .any(|chunk| &chunk.kind == b"acTL"))
+}
+
+impl Animation {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let chunks = png::read_chunks(buffer)?;
+
+        let mut decoder = ::png::Decoder::new(buffer);
+        decoder.set_transformations(::png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+        let mut frames = Vec::new();
+        let mut data = vec![0; reader.output_buffer_size()];
+        for _ in 0..frame_groups(&chunks).len() {
+            let info = reader.next_frame(&mut data)?;
+            frames.push(to_image(
+                info.width,
+                info.height,
+                info.color_type,
+                info.bit_depth,
+                &data[..info.buffer_size()],
+            )?);
+        }
+        Ok(Self { chunks, frames })
+    }
+
+    /// Re-encode frame pixels into IDAT/fdAT chunks, renumbering sequence
+    /// numbers of frame control and data chunks
+    pub fn encode(&self, png_opts: &PngOptions) -> Result<Vec<u8>> {
+        let mut frame_data = Vec::with_capacity(self.frames.len());
+        for frame in &self.frames {
+            let mut buffer = Vec::new();
+            PngEncoder::new_with_quality(&mut buffer, png_opts.compression, png_opts.filter)
+                .write_image(
+                    frame.as_bytes(),
+                    frame.width(),
+                    frame.height(),
+                    frame.color().into(),
+                )?;
+            let data: Vec<u8> = png::read_chunks(&buffer)?
+                .into_iter()
+                .filter(|chunk| &chunk.kind == b"IDAT")
+                .flat_map(|chunk| chunk.data)
+                .collect();
+            frame_data.push(data);
+        }
+
+        let groups = frame_groups(&self.chunks);
+        let mut frame_data = frame_data.into_iter();
+        let mut sequence = 0u32;
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        for (idx, chunk) in self.chunks.iter().enumerate() {
+            match &chunk.kind {
+                // frames are written back without interlacing
+                b"IHDR" => {
+                    let mut data = chunk.data.clone();
+                    data[12] = 0;
+                    chunks.push(Chunk {
+                        kind: chunk.kind,
+                        data,
+                    });
+                }
+                b"fcTL" => {
+                    let mut data = chunk.data.clone();
+                    data[..4].copy_from_slice(&sequence.to_be_bytes());
+                    sequence += 1;
+                    chunks.push(Chunk {
+                        kind: chunk.kind,
+                        data,
+                    });
+                }
+                b"IDAT" | b"fdAT" if groups.contains(&idx) => {
+                    let Some(data) = frame_data.next() else {
+                        bail!("APNG frame count mismatch");
+                    };
+                    let data = if &chunk.kind == b"IDAT" {
+                        data
+                    } else {
+                        let mut fdat = sequence.to_be_bytes().to_vec();
+                        sequence += 1;
+                        fdat.extend_from_slice(&data);
+                        fdat
+                    };
+                    chunks.push(Chunk {
+                        kind: chunk.kind,
+                        data,
+                    });
+                }
+                b"IDAT" | b"fdAT" => {}
+                _ if png_opts.strip
+                    && !chunk.is_critical()
+                    && !CONTROL_CHUNKS.contains(&&chunk.kind) => {}
+                _ => chunks.push(chunk.clone()),
+            }
+        }
+        Ok(png::write_chunks(&chunks))
+    }
+}
+
+/// Positions of the first chunk of every frame's image data
+fn frame_groups(chunks: &[Chunk]) -> Vec<usize> {
+    let mut groups = Vec::new();
+    let mut previous = None;
+    for (idx, chunk) in chunks.iter().enumerate() {
+        let kind = Some(&chunk.kind);
+        if matches!(kind, Some(b"IDAT" | b"fdAT")) && kind != previous {
+            groups.push(idx);
+        }
+        previous = kind;
+    }
+    groups
+}
+
+fn to_image(
+    width: u32,
+    height: u32,
+    color: ::png::ColorType,
+    depth: ::png::BitDepth,
+    data: &[u8],
+) -> Result<DynamicImage> {
+    use ::png::{BitDepth, ColorType};
+
+    let image = match depth {
+        BitDepth::Eight => {
+            let data = data.to_vec();
+            match color {
+                ColorType::Grayscale => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+                }
+                ColorType::GrayscaleAlpha => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+                }
+                ColorType::Rgb => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+                }
+                ColorType::Rgba => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+                }
+                ColorType::Indexed => bail!("indexed-color APNG isn't supported"),
+            }
+        }
+        BitDepth::Sixteen => {
+            let data: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            match color {
+                ColorType::Grayscale => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+                }
+                ColorType::GrayscaleAlpha => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+                }
+                ColorType::Rgb => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+                }
+                ColorType::Rgba => {
+                    ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+                }
+                ColorType::Indexed => bail!("indexed-color APNG isn't supported"),
+            }
+        }
+        depth => bail!("unsupported APNG bit depth: {depth:?}"),
+    };
+    let Some(image) = image else {
+        bail!("invalid APNG frame size");
+    };
+    ensure!(image.width() > 0 && image.height() > 0, "empty APNG frame");
+    Ok(image)
+}
@@ -0,0 +1,79 @@
+use anyhow::{bail, ensure, Result};
+use image::{DynamicImage, ImageFormat, RgbImage};
+
+/// Fields of BMP headers needed to address the pixel array
+#[derive(Clone, Copy, Debug)]
+pub struct BmpInfo {
+    pub data_offset: usize,
+    pub width: usize,
+    pub height: i32,
+    pub bits_per_pixel: u16,
+    pub compression: u32,
+}
+
+impl BmpInfo {
+    pub fn read(buffer: &[u8]) -> Result<Self> {
+        ensure!(
+            buffer.len() >= 34 && buffer.starts_with(b"BM"),
+            "invalid BMP header"
+        );
+        let u32_at = |pos: usize| u32::from_le_bytes(buffer[pos..pos + 4].try_into().unwrap());
+        Ok(Self {
+            data_offset: u32_at(10) as usize,
+            width: u32_at(18) as usize,
+            height: u32_at(22) as i32,
+            bits_per_pixel: u16::from_le_bytes([buffer[28], buffer[29]]),
+            compression: u32_at(30),
+        })
+    }
+
+    /// Uncompressed 24-bit or 32-bit pixels, which can be rewritten in place
+    pub fn is_plain_rgb(&self) -> bool {
+        self.compression == 0 && matches!(self.bits_per_pixel, 24 | 32)
+    }
+}
+
+/// Header and pixels of a BMP cover, lower depths are palette indices and
+/// only 24-bit and 32-bit files are supported
+pub fn load(buffer: &[u8]) -> Result<(BmpInfo, DynamicImage)> {
+    let info = BmpInfo::read(buffer)?;
+    let image = image::load_from_memory_with_format(buffer, ImageFormat::Bmp)?;
+    match image {
+        DynamicImage::ImageRgb8(_) if info.is_plain_rgb() => {}
+        // 32-bit with alpha mask is written back by the image crate as is
+        DynamicImage::ImageRgba8(_) if info.bits_per_pixel == 32 => {}
+        _ => bail!(
+            "unsupported BMP: {}-bit, compression {}, only 24-bit and 32-bit are supported",
+            info.bits_per_pixel,
+            info.compression
+        ),
+    }
+    Ok((info, image))
+}
+
+/// Write RGB pixels into the pixel array of an uncompressed BMP, keeping
+/// headers, unused fourth bytes and row padding of the original file
+pub fn patch(buffer: &[u8], image: &RgbImage) -> Result<Vec<u8>> {
+    let info = BmpInfo::read(buffer)?;
+    ensure!(info.is_plain_rgb(), "BMP pixels can't be patched in place");
+    let pixel_size = info.bits_per_pixel as usize / 8;
+    let stride = (info.width * pixel_size).div_ceil(4) * 4;
+    let height = info.height.unsigned_abs() as usize;
+    ensure!(
+        buffer.len() >= info.data_offset + stride * height,
+        "truncated BMP pixel data"
+    );
+
+    let mut output = buffer.to_vec();
+    for (y, row) in image.rows().enumerate() {
+        // positive height means bottom-up rows
+        let file_row = if info.height > 0 { height - 1 - y } else { y };
+        let row_start = info.data_offset + file_row * stride;
+        for (x, pixel) in row.enumerate() {
+            let pos = row_start + x * pixel_size;
+            let [r, g, b] = pixel.0;
+            output[pos..pos + 3].copy_from_slice(&[b, g, r]);
+        }
+    }
+    Ok(output)
+}
@@ -0,0 +1,50 @@
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rand_seeder::Seeder;
+
+const MAGIC: &[u8; 4] = b"s739";
+pub const HEADER_SIZE: usize = 8;
+
+fn keystream(key: Option<String>) -> ChaCha20Rng {
+    ChaCha20Rng::from_seed(Seeder::from(key).make_seed())
+}
+
+fn apply(rng: &mut ChaCha20Rng, buffer: &mut [u8]) {
+    let mut stream = vec![0u8; buffer.len()];
+    rng.fill_bytes(&mut stream);
+    buffer
+        .iter_mut()
+        .zip(stream)
+        .for_each(|(byte, k)| *byte ^= k);
+}
+
+/// Wrap payload for containers storing data as bytes: magic, 32-bit LE length
+/// and data, XORed with a ChaCha20 keystream seeded by the key.
+///
+/// This only scrambles the payload so that it can't be told from noise, it
+/// isn't encryption: there is no nonce, so every envelope of a key reuses the
+/// same keystream, the key isn't stretched and nothing is authenticated
+pub fn seal(data: &[u8], key: Option<String>) -> Vec<u8> {
+    let mut sealed = Vec::with_capacity(HEADER_SIZE + data.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    sealed.extend_from_slice(data);
+    apply(&mut keystream(key), &mut sealed);
+    sealed
+}
+
+/// Payload of the envelope at the start of `buffer`, `None` if there is no
+/// envelope for this key
+pub fn open(buffer: &[u8], key: Option<String>) -> Option<Vec<u8>> {
+    let mut header: [u8; HEADER_SIZE] = buffer.get(..HEADER_SIZE)?.try_into().ok()?;
+    let mut rng = keystream(key);
+    apply(&mut rng, &mut header);
+    if &header[..4] != MAGIC {
+        return None;
+    }
+
+    let len = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+    let mut data = buffer.get(HEADER_SIZE..HEADER_SIZE + len)?.to_vec();
+    apply(&mut rng, &mut data);
+    Some(data)
+}
@@ -0,0 +1,342 @@
+use std::io::Cursor;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::utils::pcm::Pcm;
+
+const STREAMINFO: u8 = 0;
+const SEEKTABLE: u8 = 3;
+const STREAMINFO_SIZE: usize = 34;
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+
+pub fn is_flac(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"fLaC")
+}
+
+/// Raw metadata block of a FLAC stream
+pub struct Block {
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+/// FLAC stream decoded to PCM, metadata blocks (Vorbis comments, pictures,
+/// ...) kept for output
+pub struct Flac {
+    blocks: Vec<Block>,
+    pub pcm: Pcm,
+}
+
+impl Flac {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let blocks = read_blocks(buffer)?;
+        let mut reader = claxon::FlacReader::new(Cursor::new(buffer))?;
+        let info = reader.streaminfo();
+        ensure!(
+            info.bits_per_sample <= 24,
+            "unsupported FLAC: {}-bit, up to 24-bit is supported",
+            info.bits_per_sample
+        );
+        let samples = reader.samples().collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            blocks,
+            pcm: Pcm {
+                channels: info.channels as usize,
+                bits_per_sample: info.bits_per_sample as usize,
+                samples,
+            },
+        })
+    }
+
+    /// Re-encode samples with fixed predictor subframes, STREAMINFO is
+    /// updated, seek table is dropped as its offsets no longer match
+    pub fn encode(&self) -> Vec<u8> {
+        let Pcm {
+            channels,
+            bits_per_sample,
+            ref samples,
+        } = self.pcm;
+        let mut frames = Vec::new();
+        let (mut min_frame, mut max_frame) = (u32::MAX, 0);
+        for (number, block) in samples.chunks(BLOCK_SIZE * channels).enumerate() {
+            let start = frames.len();
+            write_frame(&mut frames, number as u64, block, channels, bits_per_sample);
+            let size = (frames.len() - start) as u32;
+            min_frame = min_frame.min(size);
+            max_frame = max_frame.max(size);
+        }
+
+        let mut buffer = b"fLaC".to_vec();
+        let blocks: Vec<&Block> = self
+            .blocks
+            .iter()
+            .filter(|block| block.kind != SEEKTABLE)
+            .collect();
+        for (idx, block) in blocks.iter().enumerate() {
+            let mut data = block.data.clone();
+            if block.kind == STREAMINFO {
+                let block_size = (samples.len() / channels).clamp(1, BLOCK_SIZE) as u16;
+                data[0..2].copy_from_slice(&block_size.to_be_bytes());
+                data[2..4].copy_from_slice(&block_size.to_be_bytes());
+                data[4..7].copy_from_slice(&min_frame.min(max_frame).to_be_bytes()[1..]);
+                data[7..10].copy_from_slice(&max_frame.to_be_bytes()[1..]);
+                data[18..34].copy_from_slice(&md5sum(&self.pcm));
+            }
+            let last = if idx + 1 == blocks.len() { 0x80 } else { 0 };
+            buffer.push(last | block.kind);
+            buffer.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+            buffer.extend_from_slice(&data);
+        }
+        buffer.extend_from_slice(&frames);
+        buffer
+    }
+}
+
+fn read_blocks(buffer: &[u8]) -> Result<Vec<Block>> {
+    ensure!(is_flac(buffer), "invalid FLAC header");
+    let mut blocks = Vec::new();
+    let mut pos = 4;
+    loop {
+        ensure!(buffer.len() >= pos + 4, "truncated FLAC metadata");
+        let header = buffer[pos];
+        let len = u32::from_be_bytes([0, buffer[pos + 1], buffer[pos + 2], buffer[pos + 3]]);
+        let end = pos + 4 + len as usize;
+        ensure!(buffer.len() >= end, "truncated FLAC metadata");
+        blocks.push(Block {
+            kind: header & 0x7F,
+            data: buffer[pos + 4..end].to_vec(),
+        });
+        pos = end;
+        if header & 0x80 != 0 {
+            break;
+        }
+    }
+    match blocks.first() {
+        Some(block) if block.kind == STREAMINFO && block.data.len() == STREAMINFO_SIZE => {
+            Ok(blocks)
+        }
+        _ => bail!("FLAC without STREAMINFO"),
+    }
+}
+
+/// MD5 of the interleaved little endian samples, as stored in STREAMINFO
+fn md5sum(pcm: &Pcm) -> [u8; 16] {
+    let width = pcm.bits_per_sample.div_ceil(8);
+    let mut context = md5::Context::new();
+    let mut bytes = Vec::with_capacity(BLOCK_SIZE * width);
+    for chunk in pcm.samples.chunks(BLOCK_SIZE) {
+        bytes.clear();
+        for sample in chunk {
+            bytes.extend_from_slice(&sample.to_le_bytes()[..width]);
+        }
+        context.consume(&bytes);
+    }
+    context.compute().0
+}
+
+/// MSB-first bit writer for frame headers and subframes
+struct BitWriter<'a> {
+    buffer: &'a mut Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(buffer: &'a mut Vec<u8>) -> Self {
+        Self {
+            buffer,
+            acc: 0,
+            len: 0,
+        }
+    }
+
+    /// Write the lowest `bits` bits of `value`
+    fn write(&mut self, value: u64, bits: u32) {
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.buffer.push((self.acc >> self.len) as u8);
+        }
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros > 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+}
+
+fn write_frame(
+    buffer: &mut Vec<u8>,
+    number: u64,
+    block: &[i32],
+    channels: usize,
+    bits_per_sample: usize,
+) {
+    let start = buffer.len();
+    let block_size = block.len() / channels;
+    // fixed blocking, block size as 16-bit at the end of the header, sample
+    // rate from STREAMINFO, independent channels
+    let sample_size = match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        _ => 0b000,
+    };
+    buffer.extend_from_slice(&[
+        0xFF,
+        0xF8,
+        0x70,
+        ((channels - 1) << 4 | sample_size << 1) as u8,
+    ]);
+    write_utf8(buffer, number);
+    buffer.extend_from_slice(&(block_size as u16 - 1).to_be_bytes());
+    buffer.push(crc8(&buffer[start..]));
+
+    let mut writer = BitWriter::new(buffer);
+    let mut channel = Vec::with_capacity(block_size);
+    // embedding into the top bit doesn't extend the sign
+    let shift = i64::BITS - bits_per_sample as u32;
+    for idx in 0..channels {
+        channel.clear();
+        channel.extend(
+            block
+                .iter()
+                .skip(idx)
+                .step_by(channels)
+                .map(|&s| (s as i64) << shift >> shift),
+        );
+        write_subframe(&mut writer, &channel, bits_per_sample as u32);
+    }
+    writer.align();
+    let crc = crc16(&buffer[start..]);
+    buffer.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Fixed predictor subframe of the cheapest order, verbatim when residual
+/// coding doesn't pay off
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32) {
+    let mut best: Option<(usize, Vec<u64>, u32, u64)> = None;
+    for order in 0..=MAX_FIXED_ORDER.min(samples.len().saturating_sub(1)) {
+        let residuals: Vec<u64> = samples
+            .windows(order + 1)
+            .map(|window| fold(window[order] - predict(window, order)))
+            .collect();
+        let (param, cost) = rice_parameter(&residuals);
+        let cost = cost + order as u64 * bits as u64;
+        if best.as_ref().is_none_or(|best| cost < best.3) {
+            best = Some((order, residuals, param, cost));
+        }
+    }
+
+    match best {
+        Some((order, residuals, param, cost)) if cost < samples.len() as u64 * bits as u64 => {
+            writer.write(0b0001000 | order as u64, 7);
+            writer.write(0, 1);
+            for &sample in &samples[..order] {
+                writer.write(sample as u64, bits);
+            }
+            // RICE2 with 5-bit parameters once 4 bits don't fit, partition order 0
+            let (method, param_bits) = if param < 15 { (0, 4) } else { (1, 5) };
+            writer.write(method, 2);
+            writer.write(0, 4);
+            writer.write(param as u64, param_bits);
+            for residual in residuals {
+                writer.write_unary(residual >> param);
+                if param > 0 {
+                    writer.write(residual, param);
+                }
+            }
+        }
+        _ => {
+            writer.write(0b0000001, 7);
+            writer.write(0, 1);
+            for &sample in samples {
+                writer.write(sample as u64, bits);
+            }
+        }
+    }
+}
+
+/// Prediction of the last sample of `window` from the ones before it
+fn predict(window: &[i64], order: usize) -> i64 {
+    let s = |back: usize| window[order - back];
+    match order {
+        0 => 0,
+        1 => s(1),
+        2 => 2 * s(1) - s(2),
+        3 => 3 * s(1) - 3 * s(2) + s(3),
+        _ => 4 * s(1) - 6 * s(2) + 4 * s(3) - s(4),
+    }
+}
+
+fn fold(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+/// Rice parameter close to the optimum and its cost in bits
+fn rice_parameter(residuals: &[u64]) -> (u32, u64) {
+    let cost = |param: u32| {
+        residuals.len() as u64 * (param as u64 + 1)
+            + residuals.iter().map(|r| r >> param).sum::<u64>()
+    };
+    let mean = residuals.iter().sum::<u64>() / residuals.len().max(1) as u64;
+    let guess = (u64::BITS - mean.leading_zeros()).min(30);
+    (guess.saturating_sub(1)..=(guess + 1).min(30))
+        .map(|param| (param, cost(param)))
+        .min_by_key(|(_, cost)| *cost)
+        .unwrap_or((0, 0))
+}
+
+/// Frame number in the extended UTF-8 coding used by FLAC headers
+fn write_utf8(buffer: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        buffer.push(value as u8);
+        return;
+    }
+    let n = (1..6).find(|n| value < 1 << (5 * n + 6)).unwrap_or(6);
+    buffer.push((0xFF00u16 >> (n + 1)) as u8 | (value >> (6 * n)) as u8);
+    for idx in (0..n).rev() {
+        buffer.push(0x80 | (value >> (6 * idx) & 0x3F) as u8);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                crc << 1 ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use anyhow::{ensure, Context, Result};
+use gif::{ColorOutput, DecodeOptions, Frame, Repeat};
+
+use crate::utils::palette::PaletteOrder;
+
+/// GIF with palette indices of every frame, frame timing and disposal
+#[derive(Clone)]
+pub struct Animation {
+    pub width: u16,
+    pub height: u16,
+    pub global_palette: Option<Vec<u8>>,
+    pub background: Option<u8>,
+    pub repeat: Repeat,
+    pub frames: Vec<Frame<'static>>,
+}
+
+impl Animation {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let mut options = DecodeOptions::new();
+        options.set_color_output(ColorOutput::Indexed);
+        let mut decoder = options.read_info(buffer)?;
+
+        let global_palette = decoder.global_palette().map(|palette| palette.to_vec());
+        let background = decoder.bg_color().map(|idx| idx as u8);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame()? {
+            frames.push(frame.clone());
+        }
+        ensure!(!frames.is_empty(), "GIF has no frames");
+        Ok(Self {
+            width: decoder.width(),
+            height: decoder.height(),
+            global_palette,
+            background,
+            repeat: decoder.repeat(),
+            frames,
+        })
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        {
+            let palette = self.global_palette.as_deref().unwrap_or(&[]);
+            let mut encoder = gif::Encoder::new(&mut buffer, self.width, self.height, palette)?;
+            // no NETSCAPE extension in the source
+            if self.repeat != Repeat::Finite(0) {
+                encoder.set_repeat(self.repeat)?;
+            }
+            for frame in &self.frames {
+                if frame.interlaced {
+                    let mut frame = frame.clone();
+                    frame.buffer = Cow::Owned(interlace(&frame.buffer, frame.width as usize));
+                    encoder.write_frame(&frame)?;
+                } else {
+                    encoder.write_frame(frame)?;
+                }
+            }
+        }
+        // logical screen descriptor, the encoder always writes 0
+        if let (Some(background), Some(_)) = (self.background, &self.global_palette) {
+            buffer[11] = background;
+        }
+        Ok(buffer)
+    }
+
+    /// Palette order of every frame, from its local or the global palette,
+    /// with the transparent index sorted first
+    pub fn orders(&self) -> Result<Vec<PaletteOrder>> {
+        self.frames
+            .iter()
+            .map(|frame| {
+                let palette = frame
+                    .palette
+                    .as_deref()
+                    .or(self.global_palette.as_deref())
+                    .context("GIF frame has no palette")?;
+                let trns = frame.transparent.map(|idx| {
+                    let mut trns = vec![u8::MAX; palette.len() / 3];
+                    if let Some(alpha) = trns.get_mut(idx as usize) {
+                        *alpha = 0;
+                    }
+                    trns
+                });
+                let order = PaletteOrder::new(palette, trns.as_deref())?;
+                // transparent pixels and their rank pair are never used, so
+                // that no pixel becomes transparent or opaque
+                Ok(match frame.transparent {
+                    Some(_) => order.reserving(2),
+                    None => order,
+                })
+            })
+            .collect()
+    }
+}
+
+pub fn is_animated(buffer: &[u8]) -> Result<bool> {
+    let mut decoder = DecodeOptions::new().read_info(buffer)?;
+    Ok(decoder.read_next_frame()?.is_some() && decoder.read_next_frame()?.is_some())
+}
+
+/// Reorder deinterlaced rows into the 4-pass GIF interlace order
+fn interlace(buffer: &[u8], width: usize) -> Vec<u8> {
+    let rows: Vec<&[u8]> = buffer.chunks(width).collect();
+    [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .into_iter()
+        .flat_map(|(start, step)| rows.iter().skip(start).step_by(step))
+        .flat_map(|row| row.iter().copied())
+        .collect()
+}
@@ -3,27 +3,27 @@ use std::slice::{Iter, IterMut};
 
 use anyhow::{bail, Result};
 use bitvec::prelude::*;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rand_seeder::Seeder;
 
 use crate::options::ExtraArgs;
+use crate::utils::lsb::Lsb;
 
 use super::jpeg::selective_check;
 use std::ops::BitAnd;
 
-pub fn get_n_bits<T: From<u8>>(
-    data_iter: &mut bitvec::slice::Iter<'_, u8, Lsb0>,
-    n_bits: usize,
-) -> Result<T> {
-    let mut bits: u8 = 0;
+pub fn get_n_bits(data_iter: &mut bitvec::slice::Iter<'_, u8, Lsb0>, n_bits: usize) -> Result<u16> {
+    let mut bits: u16 = 0;
     for i in (0..n_bits).rev() {
         let bit = match data_iter.next() {
             Some(bit) => bit,
-            None if i == n_bits => bail!("no more data"),
-            None => return Ok(bits.into()),
+            None if i == n_bits - 1 => bail!("no more data"),
+            None => return Ok(bits),
         };
         bits |= (if *bit { 1 } else { 0 }) << i;
     }
-    Ok(bits.into())
+    Ok(bits)
 }
 
 pub fn set_n_bits<T>(
@@ -53,6 +53,40 @@ pub fn rand_step<R: Rng>(rng: &mut R, max_step: usize) -> usize {
     }
 }
 
+/// Skip `seek` items, then walk with random steps below `max_step` seeded by `key`
+pub fn rand_steps<I: Iterator>(
+    mut iter: I,
+    key: Option<String>,
+    seek: usize,
+    max_step: usize,
+) -> impl Iterator<Item = I::Item> {
+    let mut rng = ChaCha20Rng::from_seed(Seeder::from(key).make_seed());
+    if seek > 0 {
+        iter.nth(seek - 1);
+    }
+    std::iter::from_fn(move || iter.nth(rand_step(&mut rng, max_step)))
+}
+
+/// Box keyed walk over units for embedding
+pub fn units_mut<'a, T: Lsb + 'a>(
+    iter: impl Iterator<Item = &'a mut T> + 'a,
+    key: Option<String>,
+    seek: usize,
+    max_step: usize,
+) -> Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a> {
+    Box::new(rand_steps(iter, key, seek, max_step).map(|unit| unit as &mut dyn Lsb))
+}
+
+/// Box keyed walk over units for extraction
+pub fn units<'a, T: Lsb + 'a>(
+    iter: impl Iterator<Item = &'a T> + 'a,
+    key: Option<String>,
+    seek: usize,
+    max_step: usize,
+) -> Box<dyn Iterator<Item = &'a dyn Lsb> + 'a> {
+    Box::new(rand_steps(iter, key, seek, max_step).map(|unit| unit as &dyn Lsb))
+}
+
 pub struct JpegBlockIter<'a> {
     row_iter: Iter<'a, (*mut [i16; 64], usize)>,
     current_row: Option<&'a (*mut [i16; 64], usize)>,
@@ -174,11 +208,11 @@ impl<'a> Iterator for JpegCoefIterMut<'a> {
 }
 
 impl super::jpeg::Blocks {
-    pub fn iter(&self, extra: ExtraArgs) -> JpegCoefIter {
+    pub fn iter(&self, extra: ExtraArgs) -> JpegCoefIter<'_> {
         JpegCoefIter::new(self, extra)
     }
 
-    pub fn iter_mut(&self, extra: ExtraArgs) -> JpegCoefIterMut {
+    pub fn iter_mut(&self, extra: ExtraArgs) -> JpegCoefIterMut<'_> {
         JpegCoefIterMut::new(self, extra)
     }
 }
@@ -1,11 +1,21 @@
-use anyhow::{ensure, Result};
+use std::ops::Range;
+
+use anyhow::{ensure, Context, Result};
+use image::DynamicImage;
 use mozjpeg_sys::{
-    boolean, jpeg_c_set_int_param, jpeg_compress_struct, jpeg_create_compress,
-    jpeg_create_decompress, jpeg_decompress_struct, jpeg_error_mgr, jpeg_mem_dest, jpeg_mem_src,
-    jpeg_read_coefficients, jpeg_read_header, jpeg_std_error, jvirt_barray_control, J_INT_PARAM,
+    boolean, jpeg_add_quant_table, jpeg_c_set_bool_param, jpeg_c_set_int_param,
+    jpeg_compress_struct, jpeg_create_compress, jpeg_create_decompress, jpeg_decompress_struct,
+    jpeg_destroy_compress, jpeg_destroy_decompress, jpeg_error_mgr, jpeg_finish_compress,
+    jpeg_marker, jpeg_mem_dest, jpeg_mem_src, jpeg_quality_scaling, jpeg_read_coefficients,
+    jpeg_read_header, jpeg_save_markers, jpeg_set_defaults, jpeg_set_quality,
+    jpeg_simple_progression, jpeg_start_compress, jpeg_std_error, jpeg_write_marker,
+    jpeg_write_scanlines, jvirt_barray_control, J_BOOLEAN_PARAM, J_COLOR_SPACE, J_INT_PARAM,
 };
 
-use crate::options::{ExtraArgs, JpegOptions};
+use crate::options::{ExtraArgs, JpegOptions, Mode, Subsampling};
+
+/// Quality when neither a quality nor custom tables are given
+const DEFAULT_QUALITY: u8 = 75;
 
 pub struct Blocks(Vec<(*mut [i16; 64], usize)>);
 type DecompressedJpeg = (
@@ -15,20 +25,58 @@ type DecompressedJpeg = (
     Blocks,
 );
 
+/// Coding parameters of the source file which aren't carried over by
+/// `jpeg_copy_critical_parameters`
+#[derive(Clone, Debug, Default)]
+pub struct SourceParams {
+    pub progressive: bool,
+    pub restart_interval: u16,
+    pub huffman_tables: Vec<HuffmanTable>,
+}
+
+#[derive(Clone, Debug)]
+pub struct HuffmanTable {
+    pub class: u8,
+    pub id: u8,
+    pub bits: [u8; 16],
+    pub values: Vec<u8>,
+}
+
 impl Blocks {
     pub fn inner(&self) -> &Vec<(*mut [i16; 64], usize)> {
         &self.0
     }
 }
 
+/// Error manager on the heap, as libjpeg calls it through the struct long
+/// after its creation, freed by `destroy_compress`/`destroy_decompress`
+unsafe fn error_mgr() -> *mut jpeg_error_mgr {
+    let err: *mut jpeg_error_mgr = Box::into_raw(Box::new(std::mem::zeroed()));
+    jpeg_std_error(&mut *err)
+}
+
+pub unsafe fn destroy_compress(cinfo: &mut jpeg_compress_struct) {
+    jpeg_destroy_compress(cinfo);
+    drop(Box::from_raw(cinfo.common.err));
+}
+
+pub unsafe fn destroy_decompress(cinfo: &mut jpeg_decompress_struct) {
+    jpeg_destroy_decompress(cinfo);
+    drop(Box::from_raw(cinfo.common.err));
+}
+
 pub unsafe fn decompress(buffer: &[u8], extra: &ExtraArgs) -> Result<DecompressedJpeg> {
-    let mut err: jpeg_error_mgr = std::mem::zeroed();
     let mut cinfo: jpeg_decompress_struct = std::mem::zeroed();
-    cinfo.common.err = jpeg_std_error(&mut err);
+    cinfo.common.err = error_mgr();
     jpeg_create_decompress(&mut cinfo);
 
     jpeg_mem_src(&mut cinfo, buffer.as_ptr(), buffer.len().try_into()?);
 
+    jpeg_save_markers(&mut cinfo, jpeg_marker::COM as i32, 0xFFFF);
+    for app in 0..16 {
+        jpeg_save_markers(&mut cinfo, jpeg_marker::APP0 as i32 + app, 0xFFFF);
+    }
+
     jpeg_read_header(&mut cinfo, true as boolean);
     let coefs_ptr = jpeg_read_coefficients(&mut cinfo);
     let (blocks, total_size) = get_blocks(&mut cinfo, coefs_ptr, extra.jpeg_comp)?;
@@ -40,9 +88,8 @@ pub unsafe fn compress(
     buffer_ptr: *mut *mut u8,
     buffer_size: *mut libc::c_ulong,
 ) -> jpeg_compress_struct {
-    let mut err: jpeg_error_mgr = std::mem::zeroed();
     let mut cinfo: jpeg_compress_struct = std::mem::zeroed();
-    cinfo.common.err = jpeg_std_error(&mut err);
+    cinfo.common.err = error_mgr();
     jpeg_create_compress(&mut cinfo);
 
     jpeg_mem_dest(&mut cinfo, buffer_ptr, buffer_size);
@@ -50,6 +97,46 @@ pub unsafe fn compress(
     cinfo
 }
 
+/// Compress pixels to a baseline JPEG with the quality and quantization of
+/// the options, alpha is dropped and 16-bit samples are reduced to 8-bit
+pub unsafe fn compress_pixels(image: &DynamicImage, jpeg_options: &JpegOptions) -> Result<Vec<u8>> {
+    ensure!(
+        jpeg_options.quant_tables.len() <= 2,
+        "at most 2 JPEG quantization tables (luma, chroma) are supported"
+    );
+    let (pixels, components, color_space) = match image.color().has_color() {
+        true => (image.to_rgb8().into_raw(), 3, J_COLOR_SPACE::JCS_RGB),
+        false => (image.to_luma8().into_raw(), 1, J_COLOR_SPACE::JCS_GRAYSCALE),
+    };
+    let buffer_ptr: *mut *mut u8 = &mut [0u8; 0].as_mut_ptr();
+    let buffer_size: *mut libc::c_ulong = &mut 0;
+    let mut cinfo = compress(buffer_ptr, buffer_size);
+
+    cinfo.image_width = image.width();
+    cinfo.image_height = image.height();
+    cinfo.input_components = components;
+    cinfo.in_color_space = color_space;
+    // the profile has to be set before the defaults it selects
+    set_options(&mut cinfo, jpeg_options);
+    jpeg_set_defaults(&mut cinfo);
+    set_quantization(&mut cinfo, jpeg_options);
+
+    jpeg_start_compress(&mut cinfo, true as boolean);
+    let stride = image.width() as usize * components as usize;
+    for row in pixels.chunks_exact(stride) {
+        let row_ptr = row.as_ptr();
+        jpeg_write_scanlines(&mut cinfo, &row_ptr, 1);
+    }
+    jpeg_finish_compress(&mut cinfo);
+    destroy_compress(&mut cinfo);
+
+    Ok(Vec::from_raw_parts(
+        *buffer_ptr,
+        *buffer_size as usize,
+        *buffer_size as usize,
+    ))
+}
+
 pub unsafe fn get_blocks(
     cinfo: &mut jpeg_decompress_struct,
     coefs_ptr: *mut *mut jvirt_barray_control,
@@ -95,7 +182,158 @@ pub unsafe fn get_blocks(
     Ok((result, size as usize))
 }
 
-pub unsafe fn set_options(cinfo: &mut jpeg_compress_struct, jpeg_options: JpegOptions) {
+/// Write APPn and COM markers saved from the source, skipping JFIF and Adobe
+/// markers already emitted by the compressor
+pub unsafe fn copy_markers(srcinfo: &jpeg_decompress_struct, dstinfo: &mut jpeg_compress_struct) {
+    let mut marker_ptr = srcinfo.marker_list;
+    while let Some(marker) = marker_ptr.as_ref() {
+        let data = std::slice::from_raw_parts(marker.data, marker.data_length as usize);
+        let skip = match marker.marker {
+            0xE0 => dstinfo.write_JFIF_header != 0 && data.starts_with(b"JFIF\0"),
+            0xEE => dstinfo.write_Adobe_marker != 0 && data.starts_with(b"Adobe"),
+            _ => false,
+        };
+        if !skip {
+            jpeg_write_marker(
+                dstinfo,
+                marker.marker as i32,
+                marker.data,
+                marker.data_length,
+            );
+        }
+        marker_ptr = marker.next;
+    }
+}
+
+/// Marker segment of a JPEG file, `start` is the offset of its 0xFF byte
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub marker: u8,
+    pub start: usize,
+    pub data: Range<usize>,
+}
+
+/// Walk the marker segments of a JPEG file, skipping entropy-coded data,
+/// returns them with the offset right after EOI
+pub fn segments(buffer: &[u8]) -> Result<(Vec<Segment>, usize)> {
+    ensure!(buffer.starts_with(&[0xFF, 0xD8]), "invalid JPEG signature");
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while pos + 2 <= buffer.len() {
+        if buffer[pos] != 0xFF {
+            pos += 1;
+            continue;
+        }
+        let marker = buffer[pos + 1];
+        match marker {
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // stuffed zero byte, TEM and RSTn have no length
+            0x00 | 0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            0xD9 => return Ok((segments, pos + 2)),
+            _ => {}
+        }
+
+        let len = buffer
+            .get(pos + 2..pos + 4)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .filter(|&len| len >= 2 && pos + 2 + len <= buffer.len())
+            .context("JPEG marker segment is truncated")?;
+        segments.push(Segment {
+            marker,
+            start: pos,
+            data: pos + 4..pos + 2 + len,
+        });
+        pos += 2 + len;
+    }
+    Ok((segments, buffer.len()))
+}
+
+/// Marker of the segments holding the payload in container modes
+pub fn container_marker(mode: Mode) -> u8 {
+    match mode {
+        Mode::Comment => 0xFE,
+        _ => 0xEF,
+    }
+}
+
+pub fn source_params(buffer: &[u8]) -> Result<SourceParams> {
+    let mut params = SourceParams::default();
+    for segment in segments(buffer)?.0 {
+        let data = &buffer[segment.data];
+        match segment.marker {
+            0xC2 | 0xC6 | 0xCA | 0xCE => params.progressive = true,
+            0xDD if data.len() >= 2 => {
+                params.restart_interval = u16::from_be_bytes([data[0], data[1]])
+            }
+            0xC4 => params.huffman_tables.extend(huffman_tables(data)?),
+            _ => {}
+        }
+    }
+    Ok(params)
+}
+
+fn huffman_tables(mut data: &[u8]) -> Result<Vec<HuffmanTable>> {
+    let mut tables = Vec::new();
+    while !data.is_empty() {
+        ensure!(data.len() >= 17, "invalid JPEG Huffman table");
+        let mut bits = [0; 16];
+        bits.copy_from_slice(&data[1..17]);
+        let count = bits.iter().map(|&n| n as usize).sum::<usize>();
+        let values = data
+            .get(17..17 + count)
+            .context("invalid JPEG Huffman table")?
+            .to_vec();
+        tables.push(HuffmanTable {
+            class: data[0] >> 4,
+            id: data[0] & 0x0F,
+            bits,
+            values,
+        });
+        data = &data[17 + count..];
+    }
+    Ok(tables)
+}
+
+/// Match the scan layout, restart interval and Huffman optimization of the
+/// source, must be called after `jpeg_copy_critical_parameters`
+pub unsafe fn mimic_source(cinfo: &mut jpeg_compress_struct, source: &SourceParams) {
+    jpeg_c_set_bool_param(
+        cinfo,
+        J_BOOLEAN_PARAM::JBOOLEAN_OPTIMIZE_SCANS,
+        false as boolean,
+    );
+    if source.progressive {
+        jpeg_simple_progression(cinfo);
+    } else {
+        cinfo.num_scans = 0;
+        cinfo.scan_info = std::ptr::null();
+    }
+    cinfo.restart_interval = source.restart_interval as u32;
+
+    // the default tables are the standard ones from the JPEG spec (K.3)
+    let optimized = source.huffman_tables.iter().any(|table| {
+        let defaults = match table.class {
+            0 => &cinfo.dc_huff_tbl_ptrs,
+            _ => &cinfo.ac_huff_tbl_ptrs,
+        };
+        match defaults.get(table.id as usize).and_then(|ptr| ptr.as_ref()) {
+            Some(default) => {
+                default.bits[1..] != table.bits
+                    || default.huffval[..table.values.len()] != table.values[..]
+            }
+            None => true,
+        }
+    });
+    cinfo.optimize_coding = optimized as boolean;
+}
+
+pub unsafe fn set_options(cinfo: &mut jpeg_compress_struct, jpeg_options: &JpegOptions) {
     jpeg_c_set_int_param(
         cinfo,
         J_INT_PARAM::JINT_COMPRESS_PROFILE,
@@ -103,6 +341,58 @@ pub unsafe fn set_options(cinfo: &mut jpeg_compress_struct, jpeg_options: JpegOp
     );
 }
 
+/// Quantization, subsampling and trellis options, only meaningful when
+/// compressing pixels as copied coefs keep the tables of their source. Has to
+/// follow `jpeg_set_defaults`, which resets all of them
+unsafe fn set_quantization(cinfo: &mut jpeg_compress_struct, jpeg_options: &JpegOptions) {
+    match jpeg_options.quant_tables.as_slice() {
+        [] => jpeg_set_quality(
+            cinfo,
+            jpeg_options.quality.unwrap_or(DEFAULT_QUALITY) as i32,
+            true as boolean,
+        ),
+        tables => {
+            // explicit tables are kept as they are unless a quality is given
+            let scale = jpeg_options
+                .quality
+                .map_or(100, |quality| jpeg_quality_scaling(quality as i32));
+            // a single table is used for chroma too
+            for idx in 0..2 {
+                let table = tables[idx.min(tables.len() - 1)].map(|value| value as u32);
+                jpeg_add_quant_table(cinfo, idx as i32, table.as_ptr(), scale, true as boolean);
+            }
+        }
+    }
+
+    if cinfo.num_components == 3 {
+        let (h, v) = match jpeg_options.subsampling.unwrap_or_default() {
+            Subsampling::S420 => (2, 2),
+            Subsampling::S422 => (2, 1),
+            Subsampling::S444 => (1, 1),
+        };
+        (*cinfo.comp_info).h_samp_factor = h;
+        (*cinfo.comp_info).v_samp_factor = v;
+    }
+
+    if let Some(trellis) = jpeg_options.trellis {
+        jpeg_c_set_bool_param(
+            cinfo,
+            J_BOOLEAN_PARAM::JBOOLEAN_TRELLIS_QUANT,
+            trellis as boolean,
+        );
+    }
+    if let Some(trellis_dc) = jpeg_options.trellis_dc {
+        jpeg_c_set_bool_param(
+            cinfo,
+            J_BOOLEAN_PARAM::JBOOLEAN_TRELLIS_QUANT_DC,
+            trellis_dc as boolean,
+        );
+    }
+    if let Some(loops) = jpeg_options.trellis_loops {
+        jpeg_c_set_int_param(cinfo, J_INT_PARAM::JINT_TRELLIS_NUM_LOOPS, loops as i32);
+    }
+}
+
 pub fn selective_check(extra: &ExtraArgs, idx: usize, coef: i16) -> bool {
     extra.selective && ((idx == 0) || (coef == 0) || (coef as usize == (extra.bits << extra.depth)))
 }
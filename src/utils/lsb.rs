@@ -0,0 +1,41 @@
+/// Storage unit (pixel channel, DCT coefficient, ...) that can carry embedded bits
+pub trait Lsb {
+    /// Read `bits` bits starting from `depth`
+    fn get_bits(&self, depth: usize, bits: usize) -> u16;
+    /// Replace `bits` bits starting from `depth` with `value`
+    fn set_bits(&mut self, depth: usize, bits: usize, value: u16);
+}
+
+fn mask(bits: usize) -> u32 {
+    u32::MAX.checked_shr(u32::BITS - bits as u32).unwrap_or(0)
+}
+
+macro_rules! impl_lsb {
+    ($($ty:ty),*) => {$(
+        impl Lsb for $ty {
+            fn get_bits(&self, depth: usize, bits: usize) -> u16 {
+                ((*self as u32 >> depth) & mask(bits)) as u16
+            }
+
+            fn set_bits(&mut self, depth: usize, bits: usize, value: u16) {
+                let mask = mask(bits) << depth;
+                *self = ((*self as u32 & !mask) | ((value as u32) << depth & mask)) as $ty;
+            }
+        }
+    )*};
+}
+
+impl_lsb!(u8, u16, i16, i32);
+
+/// Little endian 16-bit sample stored as raw bytes (high bit depth Y4M)
+impl Lsb for [u8; 2] {
+    fn get_bits(&self, depth: usize, bits: usize) -> u16 {
+        u16::from_le_bytes(*self).get_bits(depth, bits)
+    }
+
+    fn set_bits(&mut self, depth: usize, bits: usize, value: u16) {
+        let mut sample = u16::from_le_bytes(*self);
+        sample.set_bits(depth, bits, value);
+        *self = sample.to_le_bytes();
+    }
+}
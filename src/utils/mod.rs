@@ -1,2 +1,18 @@
+pub mod apng;
+pub mod bmp;
+pub mod envelope;
+pub mod flac;
+pub mod gif;
 pub mod iter;
 pub mod jpeg;
+pub mod lsb;
+pub mod palette;
+pub mod pcm;
+pub mod png;
+pub mod pnm;
+pub mod raster;
+pub mod text;
+pub mod tiff;
+pub mod wav;
+pub mod webp;
+pub mod y4m;
@@ -0,0 +1,73 @@
+use anyhow::{bail, ensure, Result};
+
+/// Palette ordered by alpha and luminance (EzStego-style), so that neighbour ranks
+/// are visually close colors and rank parity can carry data
+pub struct PaletteOrder {
+    ranks: Vec<u8>,
+    indices: Vec<u8>,
+    /// Leading ranks which never carry data
+    reserved: usize,
+}
+
+impl PaletteOrder {
+    /// `palette` is packed RGB, `trns` holds optional per-entry alpha
+    pub fn new(palette: &[u8], trns: Option<&[u8]>) -> Result<Self> {
+        let len = palette.len() / 3;
+        ensure!(len >= 2, "palette is too small: {len} colors");
+        ensure!(len <= 256, "palette is too big: {len} colors");
+
+        let mut indices: Vec<u8> = (0..len as u16).map(|idx| idx as u8).collect();
+        indices.sort_by_key(|&idx| {
+            let idx = idx as usize;
+            let alpha = trns
+                .and_then(|trns| trns.get(idx))
+                .copied()
+                .unwrap_or(u8::MAX);
+            let [r, g, b] = [0, 1, 2].map(|c| palette[idx * 3 + c] as u32);
+            (alpha, 299 * r + 587 * g + 114 * b)
+        });
+
+        let mut ranks = vec![0u8; len];
+        for (rank, &idx) in indices.iter().enumerate() {
+            ranks[idx as usize] = rank as u8;
+        }
+        Ok(Self {
+            ranks,
+            indices,
+            reserved: 0,
+        })
+    }
+
+    /// Keep the first `ranks` ranks (an even count) out of embedding
+    pub fn reserving(mut self, ranks: usize) -> Self {
+        self.reserved = ranks;
+        self
+    }
+
+    /// Whether the rank carries data, both parities have to be reachable
+    /// among the ranks past the reserved ones
+    pub fn is_usable(&self, rank: u8) -> bool {
+        rank as usize >= self.reserved && self.indices.len() >= self.reserved + 2
+    }
+
+    pub fn rank(&self, index: u8) -> Result<u8> {
+        match self.ranks.get(index as usize) {
+            Some(rank) => Ok(*rank),
+            None => bail!("palette index {index} is out of range"),
+        }
+    }
+
+    /// Rank past the end (odd-sized palette) is clamped to the last rank of
+    /// the same parity, one rank away from the last one
+    pub fn index(&self, rank: u8) -> u8 {
+        let rank = rank as usize;
+        let last = self.indices.len() - 1;
+        if rank <= last {
+            self.indices[rank]
+        } else if (rank ^ last) & 1 == 0 {
+            self.indices[last]
+        } else {
+            self.indices[last - 1]
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{ensure, Result};
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+/// Interleaved integer samples of an audio stream
+pub struct Pcm {
+    pub channels: usize,
+    pub bits_per_sample: usize,
+    pub samples: Vec<i32>,
+}
+
+/// Validate channels, depth and bits, returns number of units
+pub fn check(pcm: &Pcm, extra: &ExtraArgs) -> Result<usize> {
+    ensure!(
+        extra.depth + extra.bits <= pcm.bits_per_sample,
+        "invalid depth and bits: {} + {} > {}",
+        extra.depth,
+        extra.bits,
+        pcm.bits_per_sample
+    );
+    let selected = selected_channels(pcm, extra)?;
+    let frames = pcm.samples.len() / pcm.channels;
+    let size = frames * selected.iter().filter(|selected| **selected).count();
+    ensure!(size > 32, "audio is too short");
+    Ok(size)
+}
+
+fn selected_channels(pcm: &Pcm, extra: &ExtraArgs) -> Result<Vec<bool>> {
+    match &extra.channels {
+        Some(channels) => {
+            let mut selected = vec![false; pcm.channels];
+            for &channel in channels {
+                ensure!(
+                    (channel as usize) < pcm.channels,
+                    "audio channel #{channel} doesn't exist"
+                );
+                selected[channel as usize] = true;
+            }
+            Ok(selected)
+        }
+        None => Ok(vec![true; pcm.channels]),
+    }
+}
+
+pub fn units<'a>(
+    pcm: &'a Pcm,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>> {
+    let selected = selected_channels(pcm, extra)?;
+    let channels = pcm.channels;
+    Ok(utils::iter::units(
+        pcm.samples
+            .iter()
+            .enumerate()
+            .filter(move |(idx, _)| selected[idx % channels])
+            .map(|(_, sample)| sample),
+        extra.key.clone(),
+        seek,
+        max_step,
+    ))
+}
+
+pub fn units_mut<'a>(
+    pcm: &'a mut Pcm,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>> {
+    let selected = selected_channels(pcm, extra)?;
+    let channels = pcm.channels;
+    Ok(utils::iter::units_mut(
+        pcm.samples
+            .iter_mut()
+            .enumerate()
+            .filter(move |(idx, _)| selected[idx % channels])
+            .map(|(_, sample)| sample),
+        extra.key.clone(),
+        seek,
+        max_step,
+    ))
+}
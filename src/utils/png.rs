@@ -0,0 +1,229 @@
+use anyhow::{bail, ensure, Result};
+use image::codecs::png::{CompressionType, FilterType};
+
+use crate::options::PngOptions;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Raw PNG chunk
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    pub fn is_critical(&self) -> bool {
+        self.kind[0].is_ascii_uppercase()
+    }
+}
+
+/// Ancillary chunks whose content depends on the IHDR color type and bit depth
+const COLOR_CHUNKS: [&[u8; 4]; 5] = [b"tRNS", b"sBIT", b"bKGD", b"hIST", b"sPLT"];
+
+/// Color type and bit depth of the IHDR chunk
+fn color_format(chunks: &[Chunk]) -> Option<(u8, u8)> {
+    chunks
+        .iter()
+        .find(|chunk| &chunk.kind == b"IHDR" && chunk.data.len() >= 10)
+        .map(|chunk| (chunk.data[9], chunk.data[8]))
+}
+
+/// Type of the private ancillary chunk holding the payload in container modes
+pub const PRIVATE_CHUNK: [u8; 4] = *b"tpNg";
+
+/// Split PNG file into chunks, stops at IEND
+pub fn read_chunks(buffer: &[u8]) -> Result<Vec<Chunk>> {
+    Ok(split_chunks(buffer)?.0)
+}
+
+/// Split PNG file into chunks, also returns the offset right after IEND
+pub fn split_chunks(buffer: &[u8]) -> Result<(Vec<Chunk>, usize)> {
+    ensure!(buffer.starts_with(&SIGNATURE), "invalid PNG signature");
+    let mut chunks = Vec::new();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let rest = &buffer[pos..];
+        ensure!(rest.len() >= 12, "truncated PNG chunk");
+        let len = u32::from_be_bytes(rest[..4].try_into()?) as usize;
+        ensure!(rest.len() >= len + 12, "truncated PNG chunk");
+        let chunk = Chunk {
+            kind: rest[4..8].try_into()?,
+            data: rest[8..8 + len].to_vec(),
+        };
+        pos += len + 12;
+        let end = &chunk.kind == b"IEND";
+        chunks.push(chunk);
+        if end {
+            return Ok((chunks, pos));
+        }
+    }
+}
+
+pub fn write_chunk(buffer: &mut Vec<u8>, chunk: &Chunk) {
+    buffer.extend_from_slice(&(chunk.data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(&chunk.kind);
+    buffer.extend_from_slice(&chunk.data);
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(&chunk.kind);
+    crc.update(&chunk.data);
+    buffer.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+pub fn write_chunks(chunks: &[Chunk]) -> Vec<u8> {
+    let mut buffer = SIGNATURE.to_vec();
+    for chunk in chunks {
+        write_chunk(&mut buffer, chunk);
+    }
+    buffer
+}
+
+/// Put freshly encoded critical chunks in place of the original ones,
+/// keeping ancillary chunks of the original file in their positions. Chunks
+/// tied to the color format are dropped when re-encoding changed it (RGB with
+/// tRNS or low bit depth gray expanded to 8-bit, ...)
+pub fn splice(original: &[Chunk], encoded: &[u8]) -> Result<Vec<u8>> {
+    if original.is_empty() {
+        return Ok(encoded.to_vec());
+    }
+    let encoded = read_chunks(encoded)?;
+    let color_changed = color_format(original) != color_format(&encoded);
+    let mut used = vec![false; encoded.len()];
+    let mut chunks = Vec::with_capacity(original.len() + encoded.len());
+
+    for chunk in original {
+        let mut replaced = false;
+        for (idx, new_chunk) in encoded.iter().enumerate() {
+            if new_chunk.kind == chunk.kind && !used[idx] {
+                used[idx] = true;
+                chunks.push(new_chunk.clone());
+                replaced = true;
+                if &chunk.kind != b"IDAT" {
+                    break;
+                }
+            }
+        }
+        let stale = color_changed && COLOR_CHUNKS.contains(&&chunk.kind);
+        if !replaced && !chunk.is_critical() && !stale {
+            chunks.push(chunk.clone());
+        }
+    }
+
+    let Some(first_data) = chunks.iter().position(|chunk| &chunk.kind == b"IDAT") else {
+        bail!("no image data in PNG");
+    };
+    let missing: Vec<Chunk> = encoded
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(chunk, _)| chunk.clone())
+        .collect();
+    chunks.splice(first_data..first_data, missing);
+
+    Ok(write_chunks(&chunks))
+}
+
+/// Indexed-color PNG with unpacked palette indices
+#[derive(Clone)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: png::BitDepth,
+    pub palette: Vec<u8>,
+    pub trns: Option<Vec<u8>>,
+    pub indices: Vec<u8>,
+}
+
+pub fn is_indexed(buffer: &[u8]) -> Result<bool> {
+    let reader = png::Decoder::new(buffer).read_info()?;
+    Ok(reader.info().color_type == png::ColorType::Indexed)
+}
+
+impl IndexedImage {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let mut decoder = png::Decoder::new(buffer);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+        ensure!(
+            reader.info().color_type == png::ColorType::Indexed,
+            "not an indexed PNG"
+        );
+
+        let mut buf = vec![0u8; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buf)?;
+        let info = reader.info();
+        let bit_depth = info.bit_depth;
+        let depth = bit_depth as usize;
+
+        let mut indices = Vec::with_capacity(frame.width as usize * frame.height as usize);
+        for row in buf
+            .chunks_exact(frame.line_size)
+            .take(frame.height as usize)
+        {
+            for x in 0..frame.width as usize {
+                let bit = x * depth;
+                let shift = 8 - depth - bit % 8;
+                indices.push((row[bit / 8] >> shift) & (u8::MAX >> (8 - depth)));
+            }
+        }
+
+        Ok(Self {
+            width: frame.width,
+            height: frame.height,
+            bit_depth,
+            palette: info.palette.as_deref().unwrap_or_default().to_vec(),
+            trns: info.trns.as_deref().map(<[u8]>::to_vec),
+            indices,
+        })
+    }
+
+    pub fn encode(&self, png_opts: &PngOptions) -> Result<Vec<u8>> {
+        let depth = self.bit_depth as usize;
+        let line_size = (self.width as usize * depth).div_ceil(8);
+        let mut data = vec![0u8; line_size * self.height as usize];
+        for (row, indices) in data
+            .chunks_exact_mut(line_size)
+            .zip(self.indices.chunks_exact(self.width as usize))
+        {
+            for (x, index) in indices.iter().enumerate() {
+                let bit = x * depth;
+                row[bit / 8] |= index << (8 - depth - bit % 8);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        let mut encoder = png::Encoder::new(&mut buffer, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(self.bit_depth);
+        encoder.set_palette(self.palette.as_slice());
+        if let Some(trns) = &self.trns {
+            encoder.set_trns(trns.as_slice());
+        }
+        set_options(&mut encoder, png_opts);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(buffer)
+    }
+}
+
+pub fn set_options<W: std::io::Write>(encoder: &mut png::Encoder<W>, png_opts: &PngOptions) {
+    encoder.set_compression(match png_opts.compression {
+        CompressionType::Default => png::Compression::Default,
+        CompressionType::Best => png::Compression::Best,
+        _ => png::Compression::Fast,
+    });
+    let (filter, adaptive_filter) = match png_opts.filter {
+        FilterType::NoFilter => (
+            png::FilterType::NoFilter,
+            png::AdaptiveFilterType::NonAdaptive,
+        ),
+        FilterType::Sub => (png::FilterType::Sub, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Up => (png::FilterType::Up, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Avg => (png::FilterType::Avg, png::AdaptiveFilterType::NonAdaptive),
+        FilterType::Paeth => (png::FilterType::Paeth, png::AdaptiveFilterType::NonAdaptive),
+        _ => (png::FilterType::Sub, png::AdaptiveFilterType::Adaptive),
+    };
+    encoder.set_filter(filter);
+    encoder.set_adaptive_filter(adaptive_filter);
+}
@@ -0,0 +1,57 @@
+use anyhow::{bail, Result};
+use image::codecs::pnm::{PnmDecoder, PnmHeader, PnmSubtype, SampleEncoding};
+use image::DynamicImage;
+
+/// Netpbm header kept as raw bytes, so comments survive re-encoding
+pub struct Header {
+    bytes: Vec<u8>,
+    encoding: SampleEncoding,
+}
+
+impl Header {
+    pub fn read(buffer: &[u8]) -> Result<Self> {
+        let (rest, header) = PnmDecoder::new(buffer)?.into_inner();
+        check(&header)?;
+        Ok(Self {
+            bytes: buffer[..buffer.len() - rest.len()].to_vec(),
+            encoding: header.subtype().sample_encoding(),
+        })
+    }
+
+    /// Original header followed by samples in its encoding, big endian
+    /// for binary 16-bit
+    pub fn encode(&self, image: &DynamicImage) -> Vec<u8> {
+        let mut buffer = self.bytes.clone();
+        match (self.encoding, image.as_flat_samples_u16()) {
+            (SampleEncoding::Binary, Some(samples)) => {
+                for sample in samples.samples {
+                    buffer.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            (SampleEncoding::Binary, None) => buffer.extend_from_slice(image.as_bytes()),
+            (SampleEncoding::Ascii, Some(samples)) => write_ascii(&mut buffer, samples.samples),
+            (SampleEncoding::Ascii, None) => write_ascii(&mut buffer, image.as_bytes()),
+        }
+        buffer
+    }
+}
+
+/// Samples are rescaled by the decoder unless maxval is 255 or 65535, and
+/// bitmaps have a single bit per pixel, neither survives embedding
+fn check(header: &PnmHeader) -> Result<()> {
+    if let PnmSubtype::Bitmap(_) = header.subtype() {
+        bail!("PBM bitmaps can't be used as a cover, only PGM/PPM/PAM are supported");
+    }
+    match header.maximal_sample() {
+        0xFF | 0xFFFF => Ok(()),
+        maxval => bail!("unsupported Netpbm maxval {maxval}, only 255 and 65535 are supported"),
+    }
+}
+
+fn write_ascii<T: ToString>(buffer: &mut Vec<u8>, samples: &[T]) {
+    for line in samples.chunks(16) {
+        let line: Vec<String> = line.iter().map(ToString::to_string).collect();
+        buffer.extend_from_slice(line.join(" ").as_bytes());
+        buffer.push(b'\n');
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{bail, ensure, Result};
+use image::DynamicImage;
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+macro_rules! match_buffer {
+    ($image:expr, $buf:ident => $body:expr) => {
+        match $image {
+            DynamicImage::ImageLuma8($buf) => $body,
+            DynamicImage::ImageLumaA8($buf) => $body,
+            DynamicImage::ImageRgb8($buf) => $body,
+            DynamicImage::ImageRgba8($buf) => $body,
+            DynamicImage::ImageLuma16($buf) => $body,
+            DynamicImage::ImageLumaA16($buf) => $body,
+            DynamicImage::ImageRgb16($buf) => $body,
+            DynamicImage::ImageRgba16($buf) => $body,
+            _ => bail!("invalid color format"),
+        }
+    };
+}
+
+/// Per-pixel channel selection, `None` when every channel of every pixel is used
+#[derive(Clone, Copy)]
+struct ChannelFilter {
+    selected: u8,
+    alpha: Option<usize>,
+    shift: usize,
+    max: u32,
+}
+
+impl ChannelFilter {
+    fn new(image: &DynamicImage, extra: &ExtraArgs) -> Result<Option<Self>> {
+        let color = image.color();
+        let channel_count = color.channel_count() as usize;
+        let alpha = color.has_alpha().then_some(channel_count - 1);
+        let selected = match &extra.channels {
+            Some(channels) => {
+                let mut selected = 0u8;
+                for &channel in channels {
+                    ensure!(
+                        (channel as usize) < channel_count,
+                        "image channel #{channel} doesn't exist"
+                    );
+                    selected |= 1 << channel;
+                }
+                selected
+            }
+            None => u8::MAX >> (8 - channel_count),
+        };
+
+        let skip_transparent = extra.skip_transparent && alpha.is_some();
+        if extra.channels.is_none() && !skip_transparent {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            selected,
+            alpha: alpha.filter(|_| skip_transparent),
+            shift: extra.depth + extra.bits,
+            max: u32::MAX >> (u32::BITS as usize - bit_depth(image)),
+        }))
+    }
+
+    /// Fully transparent and fully opaque pixels are skipped, alpha is used
+    /// only when the embedded value can't become 0 or max
+    fn mask(&self, pixel: &[impl Copy + Into<u32>]) -> u8 {
+        let Some(alpha) = self.alpha else {
+            return self.selected;
+        };
+        let value: u32 = pixel[alpha].into();
+        if value == 0 || value == self.max {
+            return 0;
+        }
+        let high = value.checked_shr(self.shift as u32).unwrap_or(0);
+        if high == 0 || high == self.max.checked_shr(self.shift as u32).unwrap_or(0) {
+            self.selected & !(1 << alpha)
+        } else {
+            self.selected
+        }
+    }
+}
+
+fn pixel_units<'a, T: Copy + Into<u32> + 'a>(
+    buf: &'a [T],
+    channel_count: usize,
+    filter: Option<ChannelFilter>,
+) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+    match filter {
+        None => Box::new(buf.iter()),
+        Some(filter) => Box::new(buf.chunks_exact(channel_count).flat_map(move |pixel| {
+            let mask = filter.mask(pixel);
+            pixel
+                .iter()
+                .enumerate()
+                .filter_map(move |(channel, unit)| (mask >> channel & 1 == 1).then_some(unit))
+        })),
+    }
+}
+
+fn pixel_units_mut<'a, T: Copy + Into<u32> + 'a>(
+    buf: &'a mut [T],
+    channel_count: usize,
+    filter: Option<ChannelFilter>,
+) -> Box<dyn Iterator<Item = &'a mut T> + 'a> {
+    match filter {
+        None => Box::new(buf.iter_mut()),
+        Some(filter) => Box::new(buf.chunks_exact_mut(channel_count).flat_map(move |pixel| {
+            let mask = filter.mask(pixel);
+            pixel
+                .iter_mut()
+                .enumerate()
+                .filter_map(move |(channel, unit)| (mask >> channel & 1 == 1).then_some(unit))
+        })),
+    }
+}
+
+pub fn bit_depth(image: &DynamicImage) -> usize {
+    let color = image.color();
+    (color.bits_per_pixel() / color.channel_count() as u16) as usize
+}
+
+/// Validate color format, depth and bits, returns number of units
+pub fn check(image: &DynamicImage, extra: &ExtraArgs) -> Result<usize> {
+    let size = count(image, extra)?;
+    ensure!(size > 32, "image is too small");
+    Ok(size)
+}
+
+/// Same as `check` over all frames (pages) of an animation, which are too
+/// small only as a whole
+pub fn frames_check(images: &[DynamicImage], extra: &ExtraArgs) -> Result<usize> {
+    let size = images
+        .iter()
+        .map(|image| count(image, extra))
+        .sum::<Result<usize>>()?;
+    ensure!(size > 32, "image is too small");
+    Ok(size)
+}
+
+fn count(image: &DynamicImage, extra: &ExtraArgs) -> Result<usize> {
+    let bit_depth = bit_depth(image);
+    ensure!(
+        extra.depth + extra.bits <= bit_depth,
+        "invalid depth and bits: {} + {} > {bit_depth}",
+        extra.depth,
+        extra.bits
+    );
+    let filter = ChannelFilter::new(image, extra)?;
+    let channel_count = image.color().channel_count() as usize;
+    match_buffer!(image, buf => Ok(pixel_units(buf, channel_count, filter).count()))
+}
+
+pub fn units<'a>(
+    image: &'a DynamicImage,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>> {
+    let filter = ChannelFilter::new(image, extra)?;
+    let channel_count = image.color().channel_count() as usize;
+    let key = extra.key.clone();
+    match_buffer!(image, buf => Ok(utils::iter::units(
+        pixel_units(buf, channel_count, filter),
+        key,
+        seek,
+        max_step,
+    )))
+}
+
+pub fn units_mut<'a>(
+    image: &'a mut DynamicImage,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>> {
+    let filter = ChannelFilter::new(image, extra)?;
+    let channel_count = image.color().channel_count() as usize;
+    let key = extra.key.clone();
+    match_buffer!(image, buf => Ok(utils::iter::units_mut(
+        pixel_units_mut(buf, channel_count, filter),
+        key,
+        seek,
+        max_step,
+    )))
+}
+
+/// Keyed walk over units of several images (animation frames) as one sequence
+pub fn frames_units<'a>(
+    images: &'a [DynamicImage],
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>> {
+    let mut frames: Vec<Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>> = Vec::new();
+    for image in images {
+        let filter = ChannelFilter::new(image, extra)?;
+        let channel_count = image.color().channel_count() as usize;
+        frames.push(match_buffer!(image, buf => Box::new(
+            pixel_units(buf, channel_count, filter).map(|unit| unit as &dyn Lsb),
+        )));
+    }
+    Ok(Box::new(utils::iter::rand_steps(
+        frames.into_iter().flatten(),
+        extra.key.clone(),
+        seek,
+        max_step,
+    )))
+}
+
+pub fn frames_units_mut<'a>(
+    images: &'a mut [DynamicImage],
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>> {
+    let mut frames: Vec<Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>> = Vec::new();
+    for image in images {
+        let filter = ChannelFilter::new(image, extra)?;
+        let channel_count = image.color().channel_count() as usize;
+        frames.push(match_buffer!(image, buf => Box::new(
+            pixel_units_mut(buf, channel_count, filter).map(|unit| unit as &mut dyn Lsb),
+        )));
+    }
+    Ok(Box::new(utils::iter::rand_steps(
+        frames.into_iter().flatten(),
+        extra.key.clone(),
+        seek,
+        max_step,
+    )))
+}
@@ -0,0 +1,95 @@
+use anyhow::{ensure, Result};
+
+/// Zero-width space, a 0 bit
+const ZERO: char = '\u{200B}';
+/// Zero-width non-joiner, a 1 bit
+const ONE: char = '\u{200C}';
+
+fn bits(payload: &[u8]) -> impl Iterator<Item = bool> + '_ {
+    payload
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |idx| byte >> idx & 1 == 1))
+}
+
+fn bytes(bits: impl Iterator<Item = bool>) -> Vec<u8> {
+    let bits: Vec<bool> = bits.collect();
+    bits.chunks_exact(8)
+        .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8))
+        .collect()
+}
+
+/// Bits of `payload` split into `parts` runs of nearly equal length
+fn spread(payload: &[u8], parts: usize) -> Vec<Vec<bool>> {
+    let bits: Vec<bool> = bits(payload).collect();
+    (0..parts)
+        .map(|idx| bits[idx * bits.len() / parts..(idx + 1) * bits.len() / parts].to_vec())
+        .collect()
+}
+
+/// Insert payload bits as zero-width characters after the spaces of the text,
+/// or after its first character when there are none
+pub fn embed_zero_width(text: &str, payload: &[u8]) -> Result<String> {
+    ensure!(
+        !text.contains([ZERO, ONE]),
+        "cover text already contains zero-width characters"
+    );
+    let mut gaps: Vec<usize> = text
+        .char_indices()
+        .filter(|(_, c)| *c == ' ')
+        .map(|(pos, _)| pos + 1)
+        .collect();
+    if gaps.is_empty() {
+        gaps.push(text.chars().next().map_or(0, char::len_utf8));
+    }
+
+    let mut output = String::with_capacity(text.len() + payload.len() * 8 * ZERO.len_utf8());
+    let mut start = 0;
+    let runs = spread(payload, gaps.len());
+    for (pos, run) in gaps.into_iter().zip(runs) {
+        output.push_str(&text[start..pos]);
+        output.extend(run.into_iter().map(|bit| if bit { ONE } else { ZERO }));
+        start = pos;
+    }
+    output.push_str(&text[start..]);
+    Ok(output)
+}
+
+pub fn extract_zero_width(text: &str) -> Vec<u8> {
+    bytes(text.chars().filter_map(|c| match c {
+        ZERO => Some(false),
+        ONE => Some(true),
+        _ => None,
+    }))
+}
+
+/// Line split into its content and line ending (`\n`, `\r\n` or nothing)
+fn split_ending(line: &str) -> (&str, &str) {
+    let content = line
+        .strip_suffix('\n')
+        .map_or(line, |line| line.strip_suffix('\r').unwrap_or(line));
+    line.split_at(content.len())
+}
+
+/// Replace the trailing whitespace of every line by payload bits, a space
+/// for 0 and a tab for 1
+pub fn embed_whitespace(text: &str, payload: &[u8]) -> Result<String> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    ensure!(!lines.is_empty(), "cover text has no lines");
+
+    let mut output = String::with_capacity(text.len() + payload.len() * 8);
+    for (line, run) in lines.iter().zip(spread(payload, lines.len())) {
+        let (content, ending) = split_ending(line);
+        output.push_str(content.trim_end_matches([' ', '\t']));
+        output.extend(run.into_iter().map(|bit| if bit { '\t' } else { ' ' }));
+        output.push_str(ending);
+    }
+    Ok(output)
+}
+
+pub fn extract_whitespace(text: &str) -> Vec<u8> {
+    bytes(text.split_inclusive('\n').flat_map(|line| {
+        let content = split_ending(line).0;
+        let trimmed = content.trim_end_matches([' ', '\t']);
+        content[trimmed.len()..].chars().map(|c| c == '\t')
+    }))
+}
@@ -0,0 +1,289 @@
+use std::io::Cursor;
+
+use anyhow::{bail, ensure, Result};
+use image::{DynamicImage, ImageBuffer};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::compression::{Compression, Deflate, Lzw, Packbits, Uncompressed};
+use tiff::encoder::{Rational, TiffEncoder, TiffValue};
+use tiff::tags::Tag;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_LZW: u16 = 5;
+const COMPRESSION_DEFLATE: u16 = 8;
+const COMPRESSION_OLD_DEFLATE: u16 = 32946;
+const COMPRESSION_PACKBITS: u16 = 32773;
+
+const PREDICTOR_NONE: u16 = 1;
+const PREDICTOR_HORIZONTAL: u16 = 2;
+
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+
+/// Descriptive tags carried over to the output pages
+const TEXT_TAGS: [Tag; 7] = [
+    Tag::ImageDescription,
+    Tag::Make,
+    Tag::Model,
+    Tag::Software,
+    Tag::DateTime,
+    Tag::Artist,
+    Tag::Copyright,
+];
+
+/// Per-page settings of the source file written back on output
+struct PageInfo {
+    compression: u16,
+    predictor: u16,
+    photometric: u16,
+    resolution: Option<[(u32, u32); 2]>,
+    resolution_unit: Option<u16>,
+    text: Vec<(Tag, String)>,
+}
+
+/// Multi-page TIFF, every page decoded to pixels, compression and basic
+/// tags kept for output
+pub struct Pages {
+    info: Vec<PageInfo>,
+    pub frames: Vec<DynamicImage>,
+}
+
+impl Pages {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        let mut decoder = Decoder::new(Cursor::new(buffer))?;
+        let mut info = Vec::new();
+        let mut frames = Vec::new();
+        loop {
+            info.push(read_info(&mut decoder)?);
+            frames.push(read_frame(&mut decoder)?);
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image()?;
+        }
+        Ok(Self { info, frames })
+    }
+
+    /// Single LZW page of raw pixels, gray+alpha is widened to RGBA as the
+    /// encoder doesn't support it
+    pub fn from_image(image: DynamicImage) -> Self {
+        let image = match image {
+            DynamicImage::ImageLumaA8(_) => image.into_rgba8().into(),
+            DynamicImage::ImageLumaA16(_) => image.into_rgba16().into(),
+            image => image,
+        };
+        let photometric = match image.color().has_color() {
+            true => PHOTOMETRIC_RGB,
+            false => PHOTOMETRIC_BLACK_IS_ZERO,
+        };
+        Self {
+            info: vec![PageInfo {
+                compression: COMPRESSION_LZW,
+                predictor: PREDICTOR_NONE,
+                photometric,
+                resolution: None,
+                resolution_unit: None,
+                text: Vec::new(),
+            }],
+            frames: vec![image],
+        }
+    }
+
+    /// Re-encode every page with its original compression and predictor
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer)?;
+        for (frame, info) in self.frames.iter().zip(&self.info) {
+            match info.compression {
+                COMPRESSION_NONE => write_page(&mut encoder, frame, info, Uncompressed)?,
+                COMPRESSION_LZW => write_page(&mut encoder, frame, info, Lzw)?,
+                COMPRESSION_DEFLATE | COMPRESSION_OLD_DEFLATE => {
+                    write_page(&mut encoder, frame, info, Deflate::default())?
+                }
+                COMPRESSION_PACKBITS => write_page(&mut encoder, frame, info, Packbits)?,
+                compression => bail!("unsupported TIFF compression {compression}"),
+            }
+        }
+        Ok(buffer.into_inner())
+    }
+}
+
+pub fn is_multipage(buffer: &[u8]) -> Result<bool> {
+    Ok(Decoder::new(Cursor::new(buffer))?.more_images())
+}
+
+fn read_info(decoder: &mut Decoder<Cursor<&[u8]>>) -> Result<PageInfo> {
+    let compression = decoder
+        .find_tag_unsigned(Tag::Compression)?
+        .unwrap_or(COMPRESSION_NONE);
+    ensure!(
+        matches!(
+            compression,
+            COMPRESSION_NONE
+                | COMPRESSION_LZW
+                | COMPRESSION_DEFLATE
+                | COMPRESSION_OLD_DEFLATE
+                | COMPRESSION_PACKBITS
+        ),
+        "unsupported TIFF compression {compression}"
+    );
+    let predictor = decoder
+        .find_tag_unsigned(Tag::Predictor)?
+        .unwrap_or(PREDICTOR_NONE);
+    ensure!(
+        matches!(predictor, PREDICTOR_NONE | PREDICTOR_HORIZONTAL),
+        "unsupported TIFF predictor {predictor}"
+    );
+    let photometric = decoder.get_tag_unsigned(Tag::PhotometricInterpretation)?;
+    let rational = |value| match value {
+        tiff::decoder::ifd::Value::Rational(n, d) => Some((n, d)),
+        _ => None,
+    };
+    let resolution = match (
+        decoder.find_tag(Tag::XResolution)?.and_then(rational),
+        decoder.find_tag(Tag::YResolution)?.and_then(rational),
+    ) {
+        (Some(x), Some(y)) => Some([x, y]),
+        _ => None,
+    };
+    let resolution_unit = decoder.find_tag_unsigned(Tag::ResolutionUnit)?;
+    let mut text = Vec::new();
+    for tag in TEXT_TAGS {
+        if let Ok(value) = decoder.get_tag_ascii_string(tag) {
+            text.push((tag, value));
+        }
+    }
+    Ok(PageInfo {
+        compression,
+        predictor,
+        photometric,
+        resolution,
+        resolution_unit,
+        text,
+    })
+}
+
+fn read_frame(decoder: &mut Decoder<Cursor<&[u8]>>) -> Result<DynamicImage> {
+    let (width, height) = decoder.dimensions()?;
+    let color = decoder.colortype()?;
+    let image = match (color, decoder.read_image()?) {
+        (tiff::ColorType::Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (tiff::ColorType::RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (tiff::ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (tiff::ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (tiff::ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (tiff::ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        _ => bail!("unsupported TIFF color type {color:?}"),
+    };
+    match image {
+        Some(image) => Ok(image),
+        None => bail!("invalid TIFF image data"),
+    }
+}
+
+fn write_page<D: Compression>(
+    encoder: &mut TiffEncoder<&mut Cursor<Vec<u8>>>,
+    frame: &DynamicImage,
+    info: &PageInfo,
+    compression: D,
+) -> Result<()> {
+    let (width, height) = (frame.width(), frame.height());
+    let differenced;
+    let frame = match info.predictor {
+        PREDICTOR_HORIZONTAL => {
+            differenced = horizontal_difference(frame)?;
+            &differenced
+        }
+        _ => frame,
+    };
+    match frame {
+        DynamicImage::ImageLuma8(buf) => {
+            write_typed::<colortype::Gray8, D>(encoder, (width, height), buf, info, compression)
+        }
+        DynamicImage::ImageRgb8(buf) => {
+            write_typed::<colortype::RGB8, D>(encoder, (width, height), buf, info, compression)
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            write_typed::<colortype::RGBA8, D>(encoder, (width, height), buf, info, compression)
+        }
+        DynamicImage::ImageLuma16(buf) => {
+            write_typed::<colortype::Gray16, D>(encoder, (width, height), buf, info, compression)
+        }
+        DynamicImage::ImageRgb16(buf) => {
+            write_typed::<colortype::RGB16, D>(encoder, (width, height), buf, info, compression)
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            write_typed::<colortype::RGBA16, D>(encoder, (width, height), buf, info, compression)
+        }
+        _ => bail!("invalid color format"),
+    }
+}
+
+/// Samples replaced by their difference with the same channel of the
+/// previous pixel in the row, as the encoder can't apply the predictor
+fn horizontal_difference(frame: &DynamicImage) -> Result<DynamicImage> {
+    fn difference<T: Copy>(data: &mut [T], stride: usize, channels: usize, sub: fn(T, T) -> T) {
+        for row in data.chunks_exact_mut(stride) {
+            for idx in (channels..stride).rev() {
+                row[idx] = sub(row[idx], row[idx - channels]);
+            }
+        }
+    }
+
+    let mut frame = frame.clone();
+    let channels = frame.color().channel_count() as usize;
+    let stride = frame.width() as usize * channels;
+    match &mut frame {
+        DynamicImage::ImageLuma8(buf) => difference(buf, stride, channels, u8::wrapping_sub),
+        DynamicImage::ImageRgb8(buf) => difference(buf, stride, channels, u8::wrapping_sub),
+        DynamicImage::ImageRgba8(buf) => difference(buf, stride, channels, u8::wrapping_sub),
+        DynamicImage::ImageLuma16(buf) => difference(buf, stride, channels, u16::wrapping_sub),
+        DynamicImage::ImageRgb16(buf) => difference(buf, stride, channels, u16::wrapping_sub),
+        DynamicImage::ImageRgba16(buf) => difference(buf, stride, channels, u16::wrapping_sub),
+        _ => bail!("invalid color format"),
+    }
+    Ok(frame)
+}
+
+fn write_typed<C: ColorType, D: Compression>(
+    encoder: &mut TiffEncoder<&mut Cursor<Vec<u8>>>,
+    (width, height): (u32, u32),
+    data: &[C::Inner],
+    info: &PageInfo,
+    compression: D,
+) -> Result<()>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut image = encoder.new_image_with_compression::<C, D>(width, height, compression)?;
+    let directory = image.encoder();
+    // keeps WhiteIsZero grayscale pages as they were
+    directory.write_tag(Tag::PhotometricInterpretation, info.photometric)?;
+    if info.predictor != PREDICTOR_NONE {
+        directory.write_tag(Tag::Predictor, info.predictor)?;
+    }
+    if let Some([x, y]) = info.resolution {
+        directory.write_tag(Tag::XResolution, Rational { n: x.0, d: x.1 })?;
+        directory.write_tag(Tag::YResolution, Rational { n: y.0, d: y.1 })?;
+    }
+    if let Some(unit) = info.resolution_unit {
+        directory.write_tag(Tag::ResolutionUnit, unit)?;
+    }
+    for (tag, value) in &info.text {
+        directory.write_tag(*tag, value.as_str())?;
+    }
+    image.write_data(data)?;
+    Ok(())
+}
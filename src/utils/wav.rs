@@ -0,0 +1,91 @@
+use std::ops::Range;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::utils::pcm::Pcm;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+pub fn is_wav(buffer: &[u8]) -> bool {
+    buffer.len() >= 12 && &buffer[..4] == b"RIFF" && &buffer[8..12] == b"WAVE"
+}
+
+/// 16-bit or 24-bit PCM WAV, samples widened to `i32` and written back in
+/// place, so every other chunk stays untouched
+pub struct Wav {
+    original: Vec<u8>,
+    data: Range<usize>,
+    pub pcm: Pcm,
+}
+
+impl Wav {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        ensure!(is_wav(buffer), "invalid WAV header");
+        let riff_end =
+            (u32::from_le_bytes(buffer[4..8].try_into()?) as usize + 8).min(buffer.len());
+        let mut format = None;
+        let mut data = None;
+        let mut pos = 12;
+        while pos + 8 <= riff_end {
+            let len = u32::from_le_bytes(buffer[pos + 4..pos + 8].try_into()?) as usize;
+            let body = pos + 8..(pos + 8 + len).min(riff_end);
+            match &buffer[pos..pos + 4] {
+                b"fmt " => format = Some(&buffer[body.clone()]),
+                b"data" => data = Some(body.clone()),
+                _ => {}
+            }
+            // chunks are padded to even size
+            pos = body.start + len + (len & 1);
+        }
+        let (Some(format), Some(data)) = (format, data) else {
+            bail!("WAV without fmt or data chunk");
+        };
+        ensure!(format.len() >= 16, "invalid WAV fmt chunk");
+
+        let mut tag = u16::from_le_bytes(format[..2].try_into()?);
+        if tag == FORMAT_EXTENSIBLE && format.len() >= 26 {
+            // first two bytes of the subformat GUID
+            tag = u16::from_le_bytes(format[24..26].try_into()?);
+        }
+        let channels = u16::from_le_bytes(format[2..4].try_into()?) as usize;
+        let bits_per_sample = u16::from_le_bytes(format[14..16].try_into()?) as usize;
+        ensure!(
+            tag == FORMAT_PCM && matches!(bits_per_sample, 16 | 24),
+            "unsupported WAV: format {tag}, {bits_per_sample}-bit, only 16-bit and 24-bit PCM are supported"
+        );
+        ensure!(channels > 0, "WAV without channels");
+
+        let width = bits_per_sample / 8;
+        let samples = buffer[data.clone()]
+            .chunks_exact(width)
+            .map(|sample| {
+                // sign-extend from the top byte
+                let mut bytes = [0u8; 4];
+                bytes[4 - width..].copy_from_slice(sample);
+                i32::from_le_bytes(bytes) >> (32 - bits_per_sample)
+            })
+            .collect();
+        Ok(Self {
+            original: buffer.to_vec(),
+            data,
+            pcm: Pcm {
+                channels,
+                bits_per_sample,
+                samples,
+            },
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let width = self.pcm.bits_per_sample / 8;
+        let mut buffer = self.original.clone();
+        for (chunk, sample) in buffer[self.data.clone()]
+            .chunks_exact_mut(width)
+            .zip(&self.pcm.samples)
+        {
+            chunk.copy_from_slice(&sample.to_le_bytes()[..width]);
+        }
+        buffer
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::{bail, ensure, Result};
+
+/// Raw RIFF chunk of a WebP file
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub kind: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+pub fn read_chunks(buffer: &[u8]) -> Result<Vec<Chunk>> {
+    ensure!(
+        buffer.len() >= 12 && &buffer[..4] == b"RIFF" && &buffer[8..12] == b"WEBP",
+        "invalid WebP header"
+    );
+    let riff_end = (u32::from_le_bytes(buffer[4..8].try_into()?) as usize + 8).min(buffer.len());
+    let mut chunks = Vec::new();
+    let mut rest = &buffer[12..riff_end];
+    while !rest.is_empty() {
+        ensure!(rest.len() >= 8, "truncated WebP chunk");
+        let len = u32::from_le_bytes(rest[4..8].try_into()?) as usize;
+        ensure!(rest.len() >= len + 8, "truncated WebP chunk");
+        chunks.push(Chunk {
+            kind: rest[..4].try_into()?,
+            data: rest[8..8 + len].to_vec(),
+        });
+        // chunks are padded to even size
+        rest = &rest[(len + 8 + (len & 1)).min(rest.len())..];
+    }
+    Ok(chunks)
+}
+
+pub fn write_chunks(chunks: &[Chunk]) -> Vec<u8> {
+    let mut body = b"WEBP".to_vec();
+    for chunk in chunks {
+        body.extend_from_slice(&chunk.kind);
+        body.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+        body.extend_from_slice(&chunk.data);
+        if chunk.data.len() & 1 == 1 {
+            body.push(0);
+        }
+    }
+    let mut buffer = b"RIFF".to_vec();
+    buffer.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&body);
+    buffer
+}
+
+/// Only still lossless (VP8L) images keep pixel values exactly
+pub fn is_animated(buffer: &[u8]) -> Result<bool> {
+    Ok(read_chunks(buffer)?
+        .iter()
+        .any(|chunk| &chunk.kind == b"ANIM"))
+}
+
+pub fn check_lossless(chunks: &[Chunk]) -> Result<()> {
+    for chunk in chunks {
+        match &chunk.kind {
+            b"VP8 " => {
+                bail!("lossy WebP can't be used as a cover, only lossless (VP8L) is supported")
+            }
+            b"ANIM" => bail!("animated WebP isn't supported"),
+            _ => {}
+        }
+    }
+    ensure!(
+        chunks.iter().any(|chunk| &chunk.kind == b"VP8L"),
+        "no image data in WebP"
+    );
+    Ok(())
+}
+
+/// Put freshly encoded VP8L bitstream in place of the original one, keeping
+/// VP8X, ICC profile, EXIF and XMP chunks of the original file
+pub fn splice(original: &[Chunk], encoded: &[u8]) -> Result<Vec<u8>> {
+    let Some(bitstream) = read_chunks(encoded)?
+        .into_iter()
+        .find(|chunk| &chunk.kind == b"VP8L")
+    else {
+        bail!("no image data in encoded WebP");
+    };
+    let chunks: Vec<Chunk> = original
+        .iter()
+        .map(|chunk| match &chunk.kind {
+            b"VP8L" => bitstream.clone(),
+            _ => chunk.clone(),
+        })
+        .collect();
+    Ok(write_chunks(&chunks))
+}
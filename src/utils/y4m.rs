@@ -0,0 +1,173 @@
+use std::ops::Range;
+
+use anyhow::{bail, ensure, Result};
+
+use crate::options::ExtraArgs;
+use crate::utils;
+use crate::utils::lsb::Lsb;
+
+pub fn is_y4m(buffer: &[u8]) -> bool {
+    buffer.starts_with(b"YUV4MPEG2 ")
+}
+
+/// YUV4MPEG2 video, plane samples are embedded in place so stream and frame
+/// headers stay untouched
+pub struct Video {
+    buffer: Vec<u8>,
+    /// Offset of the plane data of every frame
+    frames: Vec<usize>,
+    /// Y, U and V plane sizes in bytes
+    planes: [usize; 3],
+    bit_depth: usize,
+    bytes_per_sample: usize,
+}
+
+impl Video {
+    pub fn decode(buffer: &[u8]) -> Result<Self> {
+        ensure!(is_y4m(buffer), "invalid Y4M header");
+        let mut decoder = y4m::Decoder::new(buffer)?;
+        let colorspace = decoder.get_colorspace();
+        let planes = match decoder.read_frame() {
+            Ok(frame) => [
+                frame.get_y_plane().len(),
+                frame.get_u_plane().len(),
+                frame.get_v_plane().len(),
+            ],
+            Err(y4m::Error::EOF) => bail!("Y4M without frames"),
+            Err(err) => return Err(err.into()),
+        };
+        let frame_size: usize = planes.iter().sum();
+
+        let mut frames = Vec::new();
+        let mut pos = line_end(buffer, 0)?;
+        while pos < buffer.len() {
+            ensure!(buffer[pos..].starts_with(b"FRAME"), "invalid Y4M frame");
+            let start = line_end(buffer, pos)?;
+            ensure!(buffer.len() >= start + frame_size, "truncated Y4M frame");
+            frames.push(start);
+            pos = start + frame_size;
+        }
+        Ok(Self {
+            buffer: buffer.to_vec(),
+            frames,
+            planes,
+            bit_depth: colorspace.get_bit_depth(),
+            bytes_per_sample: colorspace.get_bytes_per_sample(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.buffer.clone()
+    }
+
+    /// Byte ranges of the selected planes over all frames, in file order
+    fn ranges(&self, extra: &ExtraArgs) -> Result<Vec<Range<usize>>> {
+        let selected = match &extra.channels {
+            Some(planes) => {
+                let mut selected = [false; 3];
+                for &plane in planes {
+                    ensure!(
+                        self.planes
+                            .get(plane as usize)
+                            .is_some_and(|size| *size > 0),
+                        "video plane #{plane} doesn't exist"
+                    );
+                    selected[plane as usize] = true;
+                }
+                selected
+            }
+            None => [true; 3],
+        };
+        let mut ranges = Vec::new();
+        for &frame in &self.frames {
+            let mut start = frame;
+            for (size, selected) in self.planes.iter().zip(selected) {
+                if selected && *size > 0 {
+                    ranges.push(start..start + size);
+                }
+                start += size;
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+fn line_end(buffer: &[u8], pos: usize) -> Result<usize> {
+    match buffer[pos..].iter().position(|&byte| byte == b'\n') {
+        Some(end) => Ok(pos + end + 1),
+        None => bail!("truncated Y4M header"),
+    }
+}
+
+/// Validate planes, depth and bits, returns number of units
+pub fn check(video: &Video, extra: &ExtraArgs) -> Result<usize> {
+    ensure!(
+        extra.depth + extra.bits <= video.bit_depth,
+        "invalid depth and bits: {} + {} > {}",
+        extra.depth,
+        extra.bits,
+        video.bit_depth
+    );
+    let bytes: usize = video.ranges(extra)?.iter().map(Range::len).sum();
+    let size = bytes / video.bytes_per_sample;
+    ensure!(size > 32, "video is too small");
+    Ok(size)
+}
+
+pub fn units<'a>(
+    video: &'a Video,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a dyn Lsb> + 'a>> {
+    let planes = video
+        .ranges(extra)?
+        .into_iter()
+        .map(|range| &video.buffer[range]);
+    let key = extra.key.clone();
+    Ok(match video.bytes_per_sample {
+        1 => utils::iter::units(planes.flat_map(|plane| plane.iter()), key, seek, max_step),
+        _ => utils::iter::units(
+            planes.flat_map(|plane| plane.as_chunks::<2>().0.iter()),
+            key,
+            seek,
+            max_step,
+        ),
+    })
+}
+
+pub fn units_mut<'a>(
+    video: &'a mut Video,
+    extra: &ExtraArgs,
+    seek: usize,
+    max_step: usize,
+) -> Result<Box<dyn Iterator<Item = &'a mut dyn Lsb> + 'a>> {
+    // split the buffer into disjoint plane slices, ranges are sorted
+    let ranges = video.ranges(extra)?;
+    let mut planes = Vec::new();
+    let mut rest = video.buffer.as_mut_slice();
+    let mut offset = 0;
+    for range in ranges {
+        let (plane, tail) = rest[range.start - offset..].split_at_mut(range.len());
+        planes.push(plane);
+        rest = tail;
+        offset = range.end;
+    }
+    let key = extra.key.clone();
+    Ok(match video.bytes_per_sample {
+        1 => utils::iter::units_mut(
+            planes.into_iter().flat_map(|plane| plane.iter_mut()),
+            key,
+            seek,
+            max_step,
+        ),
+        _ => utils::iter::units_mut(
+            planes
+                .into_iter()
+                .flat_map(|plane| plane.as_chunks_mut::<2>().0.iter_mut()),
+            key,
+            seek,
+            max_step,
+        ),
+    })
+}
@@ -1,9 +1,12 @@
+use std::io::{Read, Write};
+
 use anyhow::Result;
 use rand::distr::Alphanumeric;
 use rand::{rng, Rng};
 use s739::decode::new_decoder;
-use s739::encode::new_encoder;
-use s739::options::{ExtraArgs, ImageOptions};
+use s739::encode::{new_encoder, new_encoder_to};
+use s739::format;
+use s739::options::{ExtraArgs, ImageOptions, Mode, Subsampling};
 
 fn rand_string(size: usize) -> String {
     rng()
@@ -20,11 +23,6 @@ fn e2e(
     extra: ExtraArgs,
     rand: bool,
 ) -> Result<()> {
-    let data = rand_string(data_size);
-    let in_path = format!("/tmp/s739_in_{}.{ext}", &data[..32]);
-    let out_path = format!("/tmp/s739_out_{}.{ext}", &data[..32]);
-    let data = data.into_bytes();
-
     let mut image_buffer = image::ImageBuffer::new(image_size.0, image_size.1);
     if rand {
         let mut rng = rng();
@@ -32,7 +30,26 @@ fn e2e(
             .iter_mut()
             .for_each(|pixel| *pixel = rng.random());
     }
-    image::DynamicImage::ImageRgb8(image_buffer).save(in_path.clone())?;
+    e2e_image(
+        ext,
+        image::DynamicImage::ImageRgb8(image_buffer),
+        data_size,
+        extra,
+    )
+}
+
+fn e2e_image(
+    ext: &str,
+    image: image::DynamicImage,
+    data_size: usize,
+    extra: ExtraArgs,
+) -> Result<()> {
+    let data = rand_string(data_size);
+    let in_path = format!("/tmp/s739_in_{}.{ext}", &data[..32]);
+    let out_path = format!("/tmp/s739_out_{}.{ext}", &data[..32]);
+    let data = data.into_bytes();
+
+    image.save(in_path.clone())?;
 
     let mut encoder = new_encoder(in_path.into(), extra.clone())?;
     println!("--- {} {} {extra:?}", encoder.total_size(), data_size << 3);
@@ -278,3 +295,1777 @@ fn jpeg_selective() -> Result<()> {
     )?;
     Ok(())
 }
+
+#[test]
+fn png_grayscale() -> Result<()> {
+    for color in [
+        image::ColorType::L8,
+        image::ColorType::La8,
+        image::ColorType::La16,
+    ] {
+        e2e_image(
+            "png",
+            image::DynamicImage::new(128, 128, color),
+            128,
+            ExtraArgs::default(),
+        )?;
+    }
+    Ok(())
+}
+
+#[test]
+fn png_16bit() -> Result<()> {
+    for color in [
+        image::ColorType::L16,
+        image::ColorType::Rgb16,
+        image::ColorType::Rgba16,
+    ] {
+        for (depth, bits) in [(0, 1), (8, 8), (0, 16)] {
+            e2e_image(
+                "png",
+                image::DynamicImage::new(128, 128, color),
+                128,
+                ExtraArgs {
+                    depth,
+                    bits,
+                    ..Default::default()
+                },
+            )?;
+        }
+    }
+    let image = image::DynamicImage::new(16, 16, image::ColorType::Rgb16);
+    image.save("/tmp/s739_16bit.png")?;
+    let mut encoder = new_encoder("/tmp/s739_16bit.png".into(), ExtraArgs::default())?;
+    encoder.write_data(b"16-bit")?;
+    let buffer = encoder.encode_image(ImageOptions::default())?;
+    assert_eq!(
+        image::load_from_memory(&buffer)?.color(),
+        image::ColorType::Rgb16
+    );
+    Ok(())
+}
+
+#[test]
+fn png_8bit_wrong_bits() {
+    let result = e2e(
+        "png",
+        (128, 128),
+        128,
+        ExtraArgs {
+            bits: 9,
+            ..Default::default()
+        },
+        false,
+    );
+    assert!(result.is_err());
+}
+
+fn indexed_png(path: &str, bit_depth: png::BitDepth, colors: usize) -> Result<Vec<u8>> {
+    let mut rng = rng();
+    let palette: Vec<u8> = (0..colors * 3).map(|_| rng.random()).collect();
+    let depth = bit_depth as usize;
+    let line_size = (128 * depth).div_ceil(8);
+    let mut data = vec![0u8; line_size * 128];
+    for row in data.chunks_exact_mut(line_size) {
+        for x in 0..128 {
+            let index = rng.random_range(0..colors) as u8;
+            row[x * depth / 8] |= index << (8 - depth - x * depth % 8);
+        }
+    }
+
+    let mut encoder = png::Encoder::new(std::fs::File::create(path)?, 128, 128);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(palette.clone());
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(palette)
+}
+
+#[test]
+fn png_palette() -> Result<()> {
+    for (bit_depth, colors) in [
+        (png::BitDepth::One, 2),
+        (png::BitDepth::Four, 13),
+        (png::BitDepth::Eight, 256),
+    ] {
+        let data = rand_string(128).into_bytes();
+        let palette = indexed_png("/tmp/s739_in_palette.png", bit_depth, colors)?;
+
+        let mut encoder = new_encoder(
+            "/tmp/s739_in_palette.png".into(),
+            ExtraArgs {
+                key: Some("some key".to_string()),
+                ..Default::default()
+            },
+        )?;
+        encoder.write_data(&data)?;
+        std::fs::write(
+            "/tmp/s739_out_palette.png",
+            encoder.encode_image(ImageOptions::default())?,
+        )?;
+
+        let reader =
+            png::Decoder::new(std::fs::File::open("/tmp/s739_out_palette.png")?).read_info()?;
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        assert_eq!(reader.info().bit_depth, bit_depth);
+        assert_eq!(reader.info().palette.as_deref(), Some(palette.as_slice()));
+
+        let decoder = new_decoder(
+            "/tmp/s739_out_palette.png".into(),
+            ExtraArgs {
+                key: Some("some key".to_string()),
+                ..Default::default()
+            },
+        )?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn palette_odd_size_transparency() -> Result<()> {
+    let palette = [10, 10, 10, 120, 120, 120, 250, 250, 250];
+    let indices: Vec<u8> = (0..64 * 48).map(|_| rng().random_range(0..3)).collect();
+    let extra = ExtraArgs {
+        key: Some(rand_string(16)),
+        ..Default::default()
+    };
+
+    // 3 PNG palette entries, the brightest one can only move down a rank
+    let mut encoder = png::Encoder::new(std::fs::File::create("/tmp/s739_in_odd.png")?, 64, 48);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_palette(palette.to_vec());
+    encoder.set_trns(vec![0]);
+    encoder.write_header()?.write_image_data(&indices)?;
+    let data = rand_string(100).into_bytes();
+    let mut encoder = new_encoder("/tmp/s739_in_odd.png".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_odd.png", &output)?;
+    let mut reader = png::Decoder::new(output.as_slice()).read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    assert!(indices
+        .iter()
+        .zip(&pixels)
+        .all(|(&before, &after)| before.abs_diff(after) <= 1));
+    let decoder = new_decoder("/tmp/s739_out_odd.png".into(), extra.clone())?;
+    assert_eq!(decoder.read_data()?, data);
+
+    // GIF with 3 colors and a transparent one keeps transparency as is
+    let mut input = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut input, 64, 48, &palette)?;
+        encoder.write_frame(&gif::Frame {
+            width: 64,
+            height: 48,
+            transparent: Some(0),
+            buffer: indices.clone().into(),
+            ..Default::default()
+        })?;
+    }
+    std::fs::write("/tmp/s739_in_odd.gif", &input)?;
+    let data = rand_string(20).into_bytes();
+    let mut encoder = new_encoder("/tmp/s739_in_odd.gif".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_odd.gif", &output)?;
+    let after = &gif_frames(&output)?[0].buffer;
+    assert!(indices
+        .iter()
+        .zip(after.iter())
+        .all(|(&before, &after)| (before == 0) == (after == 0)));
+    let decoder = new_decoder("/tmp/s739_out_odd.gif".into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+#[test]
+fn png_palette_wrong_bits() -> Result<()> {
+    indexed_png("/tmp/s739_in_palette_bits.png", png::BitDepth::Eight, 256)?;
+    let result = new_encoder(
+        "/tmp/s739_in_palette_bits.png".into(),
+        ExtraArgs {
+            bits: 2,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_err());
+    Ok(())
+}
+
+fn rgba_roundtrip(image: image::RgbaImage, extra: ExtraArgs) -> Result<image::RgbaImage> {
+    let data = rand_string(128);
+    let in_path = format!("/tmp/s739_in_rgba_{}.png", &data[..32]);
+    let out_path = format!("/tmp/s739_out_rgba_{}.png", &data[..32]);
+    let data = data.into_bytes();
+    image.save(&in_path)?;
+
+    let mut encoder = new_encoder(in_path.into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    std::fs::write(&out_path, encoder.encode_image(ImageOptions::default())?)?;
+
+    let decoder = new_decoder(out_path.clone().into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(image::open(out_path)?.into_rgba8())
+}
+
+#[test]
+fn png_channels() -> Result<()> {
+    let image = image::RgbaImage::from_pixel(128, 128, image::Rgba([0, 0, 0, 255]));
+    let output = rgba_roundtrip(
+        image,
+        ExtraArgs {
+            channels: Some(vec![0, 1, 2]),
+            ..Default::default()
+        },
+    )?;
+    assert!(output.pixels().all(|pixel| pixel[3] == 255));
+
+    let result = e2e(
+        "png",
+        (128, 128),
+        128,
+        ExtraArgs {
+            channels: Some(vec![3]),
+            ..Default::default()
+        },
+        false,
+    );
+    assert!(result.is_err());
+
+    // fewer units than the size header
+    let image = image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 0, 255]));
+    image.save("/tmp/s739_in_tiny.png")?;
+    let extra = ExtraArgs {
+        channels: Some(vec![0]),
+        ..Default::default()
+    };
+    let error = new_encoder("/tmp/s739_in_tiny.png".into(), extra.clone()).err();
+    assert!(error.unwrap().to_string().contains("too small"));
+    let error = new_decoder("/tmp/s739_in_tiny.png".into(), extra).err();
+    assert!(error.unwrap().to_string().contains("too small"));
+    Ok(())
+}
+
+#[test]
+fn png_skip_transparent() -> Result<()> {
+    let mut rng = rng();
+    let image = image::RgbaImage::from_fn(128, 128, |_, _| {
+        let alpha = match rng.random_range(0..3) {
+            0 => 0,
+            1 => 255,
+            _ => rng.random(),
+        };
+        image::Rgba([rng.random(), rng.random(), rng.random(), alpha])
+    });
+    let output = rgba_roundtrip(
+        image.clone(),
+        ExtraArgs {
+            skip_transparent: true,
+            bits: 2,
+            ..Default::default()
+        },
+    )?;
+    for (before, after) in image.pixels().zip(output.pixels()) {
+        if matches!(before[3], 0 | 255) || matches!(after[3], 0 | 255) {
+            assert_eq!(before, after);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn png_metadata() -> Result<()> {
+    let mut rng = rng();
+    let data: Vec<u8> = (0..128 * 128 * 3).map(|_| rng.random()).collect();
+    let mut encoder = png::Encoder::new(std::fs::File::create("/tmp/s739_in_meta.png")?, 128, 128);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_source_gamma(png::ScaledFloat::new(0.5));
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: 3780,
+        yppu: 3780,
+        unit: png::Unit::Meter,
+    }));
+    encoder.add_text_chunk("Author".to_string(), "s739".to_string())?;
+    encoder.write_header()?.write_image_data(&data)?;
+
+    for strip in [false, true] {
+        let mut encoder = new_encoder("/tmp/s739_in_meta.png".into(), ExtraArgs::default())?;
+        encoder.write_data(b"metadata")?;
+        let mut image_opts = ImageOptions::default();
+        image_opts.png.strip = strip;
+        std::fs::write("/tmp/s739_out_meta.png", encoder.encode_image(image_opts)?)?;
+
+        let reader =
+            png::Decoder::new(std::fs::File::open("/tmp/s739_out_meta.png")?).read_info()?;
+        let info = reader.info();
+        assert_eq!(info.source_gamma.is_some(), !strip);
+        assert_eq!(info.pixel_dims.is_some(), !strip);
+        assert_eq!(
+            info.uncompressed_latin1_text.len(),
+            if strip { 0 } else { 1 }
+        );
+
+        let decoder = new_decoder("/tmp/s739_out_meta.png".into(), ExtraArgs::default())?;
+        assert_eq!(decoder.read_data()?, b"metadata");
+    }
+    Ok(())
+}
+
+#[test]
+fn png_color_chunks() -> Result<()> {
+    // RGB with a transparent color key is expanded to RGBA on decoding
+    let data: Vec<u8> = (0..128 * 128 * 3).map(|_| rng().random()).collect();
+    let mut encoder = png::Encoder::new(std::fs::File::create("/tmp/s739_in_trns.png")?, 128, 128);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_trns(vec![0, 0, 0, 0, 0, 0]);
+    encoder.set_source_gamma(png::ScaledFloat::new(0.5));
+    encoder.write_header()?.write_image_data(&data)?;
+
+    let mut encoder = new_encoder("/tmp/s739_in_trns.png".into(), ExtraArgs::default())?;
+    encoder.write_data(b"color key")?;
+    std::fs::write(
+        "/tmp/s739_out_trns.png",
+        encoder.encode_image(ImageOptions::default())?,
+    )?;
+
+    let reader = png::Decoder::new(std::fs::File::open("/tmp/s739_out_trns.png")?).read_info()?;
+    let info = reader.info();
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    assert!(info.trns.is_none());
+    assert!(info.source_gamma.is_some());
+    let decoder = new_decoder("/tmp/s739_out_trns.png".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, b"color key");
+    Ok(())
+}
+
+fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xFF, marker];
+    segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(data);
+    segment
+}
+
+#[test]
+fn jpeg_markers() -> Result<()> {
+    let mut buffer = Vec::new();
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::new(128, 128)).write_to(
+        &mut std::io::Cursor::new(&mut buffer),
+        image::ImageFormat::Jpeg,
+    )?;
+    let exif = jpeg_segment(0xE1, b"Exif\0\0MM\0\x2a\0\0\0\x08\0\0");
+    let comment = jpeg_segment(0xFE, b"s739 comment");
+    buffer.splice(2..2, exif.iter().chain(&comment).copied());
+    std::fs::write("/tmp/s739_in_markers.jpg", &buffer)?;
+
+    for strip in [false, true] {
+        let mut encoder = new_encoder("/tmp/s739_in_markers.jpg".into(), ExtraArgs::default())?;
+        encoder.write_data(b"markers")?;
+        let mut image_opts = ImageOptions::default();
+        image_opts.jpeg.strip = strip;
+        let output = encoder.encode_image(image_opts)?;
+        std::fs::write("/tmp/s739_out_markers.jpg", &output)?;
+
+        let contains = |segment: &[u8]| output.windows(segment.len()).any(|w| w == segment);
+        assert_eq!(contains(&exif), !strip);
+        assert_eq!(contains(&comment), !strip);
+
+        let decoder = new_decoder("/tmp/s739_out_markers.jpg".into(), ExtraArgs::default())?;
+        assert_eq!(decoder.read_data()?, b"markers");
+    }
+    Ok(())
+}
+
+/// Marker segments up to the first scan
+fn jpeg_header(buffer: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    while buffer[pos + 1] != 0xDA {
+        let len = u16::from_be_bytes([buffer[pos + 2], buffer[pos + 3]]) as usize;
+        segments.push((buffer[pos + 1], buffer[pos + 4..pos + 2 + len].to_vec()));
+        pos += 2 + len;
+    }
+    segments
+}
+
+#[test]
+fn jpeg_mimic_source() -> Result<()> {
+    let mut baseline = Vec::new();
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(128, 128, |x, y| {
+        image::Rgb([x as u8, y as u8, (x ^ y) as u8])
+    }))
+    .write_to(
+        &mut std::io::Cursor::new(&mut baseline),
+        image::ImageFormat::Jpeg,
+    )?;
+    std::fs::write("/tmp/s739_in_mimic.jpg", &baseline)?;
+
+    let encode = |path: &str, mimic_source: bool| -> Result<Vec<u8>> {
+        let mut encoder = new_encoder(path.into(), ExtraArgs::default())?;
+        encoder.write_data(b"mimic")?;
+        let mut image_opts = ImageOptions::default();
+        image_opts.jpeg.mimic_source = mimic_source;
+        let output = encoder.encode_image(image_opts)?;
+        std::fs::write("/tmp/s739_out_mimic.jpg", &output)?;
+        let decoder = new_decoder("/tmp/s739_out_mimic.jpg".into(), ExtraArgs::default())?;
+        assert_eq!(decoder.read_data()?, b"mimic");
+        Ok(output)
+    };
+    let kinds =
+        |buffer: &[u8]| -> Vec<u8> { jpeg_header(buffer).into_iter().map(|s| s.0).collect() };
+    let tables = |buffer: &[u8]| -> Vec<Vec<u8>> {
+        jpeg_header(buffer)
+            .into_iter()
+            .filter(|s| s.0 == 0xC4)
+            .map(|s| s.1)
+            .collect()
+    };
+
+    let progressive = encode("/tmp/s739_in_mimic.jpg", false)?;
+    assert!(kinds(&progressive).contains(&0xC2));
+
+    let output = encode("/tmp/s739_in_mimic.jpg", true)?;
+    assert!(kinds(&output).contains(&0xC0));
+    assert!(!kinds(&output).contains(&0xC2));
+    // same tables, possibly grouped into DHT segments differently
+    let output_tables = tables(&output).concat();
+    assert_eq!(output_tables.len(), tables(&baseline).concat().len());
+    for table in tables(&baseline) {
+        assert!(output_tables.windows(table.len()).any(|w| w == table));
+    }
+
+    std::fs::write("/tmp/s739_in_mimic.jpg", &progressive)?;
+    let output = encode("/tmp/s739_in_mimic.jpg", true)?;
+    assert!(kinds(&output).contains(&0xC2));
+    Ok(())
+}
+
+#[test]
+fn jpeg_container() -> Result<()> {
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::new(64, 64))
+        .save("/tmp/s739_in_container.jpg")?;
+    let input = std::fs::read("/tmp/s739_in_container.jpg")?;
+
+    for mode in [Mode::Metadata, Mode::Comment, Mode::Trailing] {
+        // large enough to span several segments
+        let data = rand_string(150_000).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            mode,
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in_container.jpg".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out_container.jpg", &output)?;
+
+        assert_eq!(
+            image::load_from_memory(&output)?.as_bytes(),
+            image::load_from_memory(&input)?.as_bytes()
+        );
+        if mode == Mode::Trailing {
+            assert!(output.starts_with(&input));
+        }
+
+        // detected without specifying the mode
+        let extra = ExtraArgs {
+            mode: Mode::Lsb,
+            ..extra
+        };
+        let decoder = new_decoder("/tmp/s739_out_container.jpg".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn png_container() -> Result<()> {
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::new(64, 64))
+        .save("/tmp/s739_in_container.png")?;
+    let input = std::fs::read("/tmp/s739_in_container.png")?;
+
+    for mode in [Mode::Metadata, Mode::Trailing] {
+        let data = rand_string(10_000).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            mode,
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in_container.png".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out_container.png", &output)?;
+
+        assert_eq!(
+            image::load_from_memory(&output)?.as_bytes(),
+            image::load_from_memory(&input)?.as_bytes()
+        );
+        // everything but IEND is kept as is
+        assert!(output.starts_with(&input[..input.len() - 12]));
+        assert_eq!(
+            output.windows(4).any(|w| w == b"tpNg"),
+            mode == Mode::Metadata
+        );
+
+        let extra = ExtraArgs {
+            mode: Mode::Lsb,
+            ..extra
+        };
+        let decoder = new_decoder("/tmp/s739_out_container.png".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+
+    let extra = ExtraArgs {
+        mode: Mode::Comment,
+        ..Default::default()
+    };
+    assert!(new_encoder("/tmp/s739_in_container.png".into(), extra).is_err());
+    Ok(())
+}
+
+/// 32-bit BMP with plain BITMAPINFOHEADER, no alpha mask
+fn bmp32(width: u32, height: u32) -> Vec<u8> {
+    let size = width * height * 4;
+    let mut buffer = b"BM".to_vec();
+    for value in [54 + size, 0, 54, 40, width, height] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    buffer.extend_from_slice(&[1, 0, 32, 0]);
+    for value in [0, size, 2835, 2835, 0, 0] {
+        buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    buffer.extend((0..size).map(|i| (i % 251) as u8));
+    buffer
+}
+
+#[test]
+fn bmp() -> Result<()> {
+    e2e("bmp", (128, 128), 1000, ExtraArgs::default(), false)?;
+    e2e_image(
+        "bmp",
+        image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(
+            64,
+            64,
+            image::Rgba([10, 20, 30, 200]),
+        )),
+        100,
+        ExtraArgs {
+            channels: Some(vec![0, 1, 2]),
+            ..Default::default()
+        },
+    )?;
+
+    let input = bmp32(64, 64);
+    std::fs::write("/tmp/s739_in_bmp32.bmp", &input)?;
+    let mut encoder = new_encoder("/tmp/s739_in_bmp32.bmp".into(), ExtraArgs::default())?;
+    encoder.write_data(b"bmp32")?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    // headers and unused fourth bytes are kept
+    assert_eq!(output[..54], input[..54]);
+    assert!((54..output.len())
+        .step_by(4)
+        .all(|i| output[i + 3] == input[i + 3]));
+    std::fs::write("/tmp/s739_out_bmp32.bmp", &output)?;
+    let decoder = new_decoder("/tmp/s739_out_bmp32.bmp".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, b"bmp32");
+
+    image::DynamicImage::ImageLuma8(image::ImageBuffer::new(64, 64))
+        .save("/tmp/s739_in_bmp8.bmp")?;
+    assert!(new_encoder("/tmp/s739_in_bmp8.bmp".into(), ExtraArgs::default()).is_err());
+    assert!(new_decoder("/tmp/s739_in_bmp8.bmp".into(), ExtraArgs::default()).is_err());
+    Ok(())
+}
+
+fn riff(form: &[u8; 4], chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut body = form.to_vec();
+    for (kind, data) in chunks {
+        body.extend_from_slice(*kind);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut buffer = b"RIFF".to_vec();
+    buffer.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&body);
+    buffer
+}
+
+#[test]
+fn webp() -> Result<()> {
+    e2e("webp", (128, 128), 1000, ExtraArgs::default(), false)?;
+    e2e_image(
+        "webp",
+        image::DynamicImage::ImageRgba8(image::ImageBuffer::from_pixel(
+            64,
+            64,
+            image::Rgba([10, 20, 30, 200]),
+        )),
+        100,
+        ExtraArgs::default(),
+    )?;
+
+    // extended format with ICC profile and EXIF around the bitstream
+    let mut simple = Vec::new();
+    image::DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(64, 64, |x, y| {
+        image::Rgb([x as u8 * 4, y as u8 * 4, 128])
+    }))
+    .write_to(
+        &mut std::io::Cursor::new(&mut simple),
+        image::ImageFormat::WebP,
+    )?;
+    let bitstream = simple[20..].to_vec();
+    let mut vp8x = vec![0x20 | 0x08, 0, 0, 0];
+    vp8x.extend_from_slice(&63u32.to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&63u32.to_le_bytes()[..3]);
+    let icc = b"fake icc profile".to_vec();
+    let exif = b"MM\0\x2a\0\0\0\x08\0\0x".to_vec();
+    let input = riff(
+        b"WEBP",
+        &[
+            (b"VP8X", vp8x),
+            (b"ICCP", icc.clone()),
+            (b"VP8L", bitstream),
+            (b"EXIF", exif.clone()),
+        ],
+    );
+    std::fs::write("/tmp/s739_in_extended.webp", &input)?;
+
+    let mut encoder = new_encoder("/tmp/s739_in_extended.webp".into(), ExtraArgs::default())?;
+    encoder.write_data(b"webp")?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_extended.webp", &output)?;
+    assert_eq!(output[12..16], *b"VP8X");
+    let contains = |segment: &[u8]| output.windows(segment.len()).any(|w| w == segment);
+    assert!(contains(&icc) && contains(&exif));
+    let decoder = new_decoder("/tmp/s739_out_extended.webp".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, b"webp");
+
+    std::fs::write(
+        "/tmp/s739_in_lossy.webp",
+        riff(b"WEBP", &[(b"VP8 ", vec![0; 32])]),
+    )?;
+    let err = new_encoder("/tmp/s739_in_lossy.webp".into(), ExtraArgs::default())
+        .err()
+        .unwrap();
+    assert!(err.to_string().contains("lossy"));
+    Ok(())
+}
+
+fn gif_frames(buffer: &[u8]) -> Result<Vec<gif::Frame<'static>>> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(buffer)?;
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame()? {
+        frames.push(frame.clone());
+    }
+    Ok(frames)
+}
+
+#[test]
+fn gif_animated() -> Result<()> {
+    let palette: Vec<u8> = (0..16u8)
+        .flat_map(|i| [i * 16, 255 - i * 16, i * 8])
+        .collect();
+    let mut input = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut input, 64, 48, &palette)?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        for i in 0..3u16 {
+            let mut frame = gif::Frame {
+                width: 64,
+                height: 48,
+                delay: 10 + i,
+                dispose: gif::DisposalMethod::Background,
+                interlaced: i == 1,
+                buffer: (0..64 * 48)
+                    .map(|p| ((p / 7 + i as usize) % 16) as u8)
+                    .collect(),
+                ..Default::default()
+            };
+            if i == 2 {
+                frame.palette = Some(palette.iter().rev().copied().collect());
+                frame.transparent = Some(3);
+            }
+            encoder.write_frame(&frame)?;
+        }
+    }
+    std::fs::write("/tmp/s739_in_animated.gif", &input)?;
+
+    let data = rand_string(500).into_bytes();
+    let extra = ExtraArgs {
+        key: Some(rand_string(16)),
+        ..Default::default()
+    };
+    let mut encoder = new_encoder("/tmp/s739_in_animated.gif".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_animated.gif", &output)?;
+
+    let (before, after) = (gif_frames(&input)?, gif_frames(&output)?);
+    assert_eq!(before.len(), after.len());
+    for (before, after) in before.iter().zip(&after) {
+        assert_eq!(before.delay, after.delay);
+        assert_eq!(before.dispose, after.dispose);
+        assert_eq!(before.interlaced, after.interlaced);
+        assert_eq!(before.transparent, after.transparent);
+        assert_eq!(before.palette, after.palette);
+        for (&old, &new) in before.buffer.iter().zip(after.buffer.iter()) {
+            assert_eq!(
+                Some(old) == before.transparent,
+                Some(new) == after.transparent
+            );
+        }
+    }
+
+    let decoder = new_decoder("/tmp/s739_out_animated.gif".into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+fn apng(sep_def_img: bool) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, 64, 64);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(3, 0)?;
+    encoder.set_sep_def_img(sep_def_img)?;
+    let mut writer = encoder.write_header()?;
+    writer.write_chunk(png::chunk::ChunkType(*b"tEXt"), b"Comment\0apng")?;
+    for i in 0..3 + sep_def_img as u8 {
+        let size = if i == 3 { 32 } else { 64 };
+        if i == 3 {
+            writer.set_frame_dimension(32, 32)?;
+            writer.set_frame_position(8, 8)?;
+        }
+        writer.set_frame_delay(i as u16 + 1, 10)?;
+        writer.set_dispose_op(png::DisposeOp::Background)?;
+        let data: Vec<u8> = (0..size * size * 4)
+            .map(|p| (p * 7 + i as usize) as u8)
+            .collect();
+        writer.write_image_data(&data)?;
+    }
+    writer.finish()?;
+    Ok(buffer)
+}
+
+fn apng_frames(buffer: &[u8]) -> Result<Vec<(Option<png::FrameControl>, Vec<u8>)>> {
+    let mut reader = png::Decoder::new(buffer).read_info()?;
+    let count = reader.info().animation_control().unwrap().num_frames as usize;
+    let mut frames = Vec::new();
+    let mut data = vec![0; reader.output_buffer_size()];
+    for _ in 0..count + (reader.info().frame_control().is_none() as usize) {
+        let info = reader.next_frame(&mut data)?;
+        frames.push((
+            reader.info().frame_control().copied(),
+            data[..info.buffer_size()].to_vec(),
+        ));
+    }
+    Ok(frames)
+}
+
+#[test]
+fn apng_frames_kept() -> Result<()> {
+    for sep_def_img in [false, true] {
+        let input = apng(sep_def_img)?;
+        std::fs::write("/tmp/s739_in_apng.png", &input)?;
+        let data = rand_string(2000).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in_apng.png".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out_apng.png", &output)?;
+
+        let (before, after) = (apng_frames(&input)?, apng_frames(&output)?);
+        assert_eq!(before.len(), after.len());
+        let mut changed = 0;
+        for ((control, pixels), (new_control, new_pixels)) in before.iter().zip(&after) {
+            assert_eq!(
+                control.map(|c| (c.width, c.height, c.x_offset, c.delay_num, c.dispose_op)),
+                new_control.map(|c| (c.width, c.height, c.x_offset, c.delay_num, c.dispose_op))
+            );
+            changed += (pixels != new_pixels) as usize;
+        }
+        // data is spread over frames
+        assert!(changed > 1);
+        assert!(output.windows(12).any(|w| w == b"Comment\0apng"));
+
+        let decoder = new_decoder("/tmp/s739_out_apng.png".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+    Ok(())
+}
+
+fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    [
+        &(data.len() as u32).to_be_bytes()[..],
+        kind,
+        data,
+        &crc.finalize().to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// Adam7 interlaced RGB scanlines, unfiltered, in a zlib stream of stored blocks
+fn adam7_zlib(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let passes = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+    let mut raw = Vec::new();
+    for (x0, y0, dx, dy) in passes {
+        for y in (y0..height).step_by(dy) {
+            if x0 >= width {
+                continue;
+            }
+            raw.push(0);
+            for x in (x0..width).step_by(dx) {
+                raw.extend_from_slice(&rgb[(y * width + x) * 3..][..3]);
+            }
+        }
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in &raw {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    let mut zlib = vec![0x78, 0x01, 0x01];
+    zlib.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+    zlib.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+    zlib.extend_from_slice(&raw);
+    zlib.extend_from_slice(&(b << 16 | a).to_be_bytes());
+    zlib
+}
+
+#[test]
+fn apng_interlaced() -> Result<()> {
+    let frames: Vec<Vec<u8>> = (0..2)
+        .map(|_| (0..16 * 16 * 3).map(|_| rng().random()).collect())
+        .collect();
+    let fctl = |sequence: u32| {
+        [
+            &sequence.to_be_bytes()[..],
+            &16u32.to_be_bytes(),
+            &16u32.to_be_bytes(),
+            &[0; 8],
+            &[0, 1, 0, 10, 0, 0],
+        ]
+        .concat()
+    };
+    let input = [
+        b"\x89PNG\r\n\x1a\n".to_vec(),
+        png_chunk(b"IHDR", &[0, 0, 0, 16, 0, 0, 0, 16, 8, 2, 0, 0, 1]),
+        png_chunk(b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+        png_chunk(b"fcTL", &fctl(0)),
+        png_chunk(b"IDAT", &adam7_zlib(16, 16, &frames[0])),
+        png_chunk(b"fcTL", &fctl(1)),
+        png_chunk(
+            b"fdAT",
+            &[&2u32.to_be_bytes()[..], &adam7_zlib(16, 16, &frames[1])].concat(),
+        ),
+        png_chunk(b"IEND", &[]),
+    ]
+    .concat();
+    std::fs::write("/tmp/s739_in_interlaced.png", &input)?;
+    assert_eq!(
+        apng_frames(&input)?
+            .into_iter()
+            .map(|f| f.1)
+            .collect::<Vec<_>>(),
+        frames
+    );
+
+    let data = rand_string(100).into_bytes();
+    let mut encoder = new_encoder("/tmp/s739_in_interlaced.png".into(), ExtraArgs::default())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_interlaced.png", &output)?;
+
+    let reader = png::Decoder::new(output.as_slice()).read_info()?;
+    assert!(!reader.info().interlaced);
+    for (before, (_, after)) in frames.iter().zip(apng_frames(&output)?) {
+        assert!(before.iter().zip(&after).all(|(a, b)| a.abs_diff(*b) <= 1));
+    }
+    let decoder = new_decoder("/tmp/s739_out_interlaced.png".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+fn tiff_pages(buffer: &[u8]) -> Result<Vec<(tiff::ColorType, u32, tiff::decoder::DecodingResult)>> {
+    let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(buffer))?;
+    let mut pages = Vec::new();
+    loop {
+        let compression = decoder.get_tag_u32(tiff::tags::Tag::Compression)?;
+        pages.push((decoder.colortype()?, compression, decoder.read_image()?));
+        if !decoder.more_images() {
+            return Ok(pages);
+        }
+        decoder.next_image()?;
+    }
+}
+
+#[test]
+fn tiff_multipage() -> Result<()> {
+    use tiff::encoder::{colortype, compression::Lzw, TiffEncoder};
+
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut input)?;
+    let gray: Vec<u16> = (0..64 * 64).map(|_| rng().random()).collect();
+    let mut page = encoder.new_image_with_compression::<colortype::Gray16, _>(64, 64, Lzw)?;
+    page.encoder()
+        .write_tag(tiff::tags::Tag::Artist, "scanner")?;
+    page.write_data(&gray)?;
+    let rgb: Vec<u8> = (0..64 * 64 * 3).map(|_| rng().random()).collect();
+    encoder.write_image_with_compression::<colortype::RGB8, _>(64, 64, Lzw, &rgb)?;
+    let input = input.into_inner();
+    std::fs::write("/tmp/s739_in_multipage.tiff", &input)?;
+
+    let data = rand_string(2000).into_bytes();
+    let extra = ExtraArgs {
+        key: Some(rand_string(16)),
+        ..Default::default()
+    };
+    let mut encoder = new_encoder("/tmp/s739_in_multipage.tiff".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_multipage.tiff", &output)?;
+
+    let (before, after) = (tiff_pages(&input)?, tiff_pages(&output)?);
+    assert_eq!(before.len(), after.len());
+    for ((color, compression, pixels), (new_color, new_compression, new_pixels)) in
+        before.iter().zip(&after)
+    {
+        assert_eq!(color, new_color);
+        assert_eq!(*compression, 5);
+        assert_eq!(compression, new_compression);
+        // data is spread over pages
+        assert!(format!("{pixels:?}") != format!("{new_pixels:?}"));
+    }
+    let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(&output))?;
+    assert_eq!(
+        decoder.get_tag_ascii_string(tiff::tags::Tag::Artist)?,
+        "scanner"
+    );
+
+    let decoder = new_decoder("/tmp/s739_out_multipage.tiff".into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+#[test]
+fn tiff_predictor_and_compression() -> Result<()> {
+    use tiff::encoder::{colortype, compression::Lzw, TiffEncoder};
+
+    // horizontal predictor applied by hand as the encoder doesn't support it
+    let rgb: Vec<u8> = (0..64 * 64 * 3).map(|_| rng().random()).collect();
+    let mut differenced = rgb.clone();
+    for row in differenced.chunks_exact_mut(64 * 3) {
+        for idx in (3..64 * 3).rev() {
+            row[idx] = row[idx].wrapping_sub(row[idx - 3]);
+        }
+    }
+    let mut input = std::io::Cursor::new(Vec::new());
+    let mut encoder = TiffEncoder::new(&mut input)?;
+    let mut page = encoder.new_image_with_compression::<colortype::RGB8, _>(64, 64, Lzw)?;
+    page.encoder().write_tag(tiff::tags::Tag::Predictor, 2u16)?;
+    page.write_data(&differenced)?;
+    std::fs::write("/tmp/s739_in_predictor.tiff", input.into_inner())?;
+
+    let data = rand_string(500).into_bytes();
+    let mut encoder = new_encoder("/tmp/s739_in_predictor.tiff".into(), ExtraArgs::default())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_predictor.tiff", &output)?;
+
+    let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(&output))?;
+    assert_eq!(decoder.get_tag_u32(tiff::tags::Tag::Predictor)?, 2);
+    let tiff::decoder::DecodingResult::U8(pixels) = decoder.read_image()? else {
+        panic!("not 8-bit");
+    };
+    assert!(pixels.iter().zip(&rgb).all(|(a, b)| a.abs_diff(*b) <= 1));
+    let decoder = new_decoder("/tmp/s739_out_predictor.tiff".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, data);
+
+    // JPEG compression is rejected before embedding
+    let mut input = std::io::Cursor::new(Vec::new());
+    TiffEncoder::new(&mut input)?.write_image::<colortype::RGB8>(64, 64, &rgb)?;
+    let mut input = input.into_inner();
+    let entry = [0x03, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00];
+    let pos = input.windows(10).position(|w| w == entry).unwrap();
+    input[pos + 8] = 7;
+    std::fs::write("/tmp/s739_in_jpeg.tiff", input)?;
+    let error = new_encoder("/tmp/s739_in_jpeg.tiff".into(), ExtraArgs::default()).err();
+    assert!(error.unwrap().to_string().contains("compression 7"));
+
+    // too few pixels over all pages
+    let mut input = std::io::Cursor::new(Vec::new());
+    TiffEncoder::new(&mut input)?.write_image::<colortype::Gray8>(4, 4, &[0; 16])?;
+    std::fs::write("/tmp/s739_in_tiny.tiff", input.into_inner())?;
+    let error = new_decoder("/tmp/s739_in_tiny.tiff".into(), ExtraArgs::default()).err();
+    assert!(error.unwrap().to_string().contains("too small"));
+    Ok(())
+}
+
+#[test]
+fn pnm() -> Result<()> {
+    let pixels = |len: usize| -> Vec<u8> { (0..len).map(|_| rng().random()).collect() };
+    let covers = [
+        // 8-bit PPM with a comment
+        [b"P6\n# scanner\n64 64\n255\n".to_vec(), pixels(64 * 64 * 3)].concat(),
+        // 16-bit PGM and PPM
+        [b"P5 64 64 65535\n".to_vec(), pixels(64 * 64 * 2)].concat(),
+        [b"P6 64 64 65535\n".to_vec(), pixels(64 * 64 * 6)].concat(),
+        // PAM
+        [
+            b"P7\nWIDTH 64\nHEIGHT 64\nDEPTH 3\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n".to_vec(),
+            pixels(64 * 64 * 3),
+        ]
+        .concat(),
+    ];
+    for input in covers {
+        std::fs::write("/tmp/s739_in.pnm", &input)?;
+        let data = rand_string(300).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in.pnm".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out.pnm", &output)?;
+
+        // header is kept byte for byte
+        assert_eq!(output.len(), input.len());
+        let header_len = input.len() - 64 * 64 * (input.len() / 64 / 64);
+        assert_eq!(output[..header_len], input[..header_len]);
+
+        let decoder = new_decoder("/tmp/s739_out.pnm".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+
+    // bitmaps and non-saturated maxval don't survive embedding
+    for input in [b"P4 8 8\n".to_vec(), b"P5 8 8 4095\n".to_vec()] {
+        std::fs::write("/tmp/s739_in_invalid.pnm", [input, vec![0; 128]].concat())?;
+        assert!(new_encoder("/tmp/s739_in_invalid.pnm".into(), ExtraArgs::default()).is_err());
+    }
+    Ok(())
+}
+
+#[test]
+fn qoi() -> Result<()> {
+    let mut image = image::RgbaImage::new(64, 64);
+    image
+        .pixels_mut()
+        .for_each(|p| *p = image::Rgba(rng().random()));
+    let mut input = Vec::new();
+    image::DynamicImage::ImageRgba8(image).write_to(
+        &mut std::io::Cursor::new(&mut input),
+        image::ImageFormat::Qoi,
+    )?;
+    // linear colorspace
+    input[13] = 1;
+    std::fs::write("/tmp/s739_in.qoi", &input)?;
+
+    let data = rand_string(1000).into_bytes();
+    let mut encoder = new_encoder("/tmp/s739_in.qoi".into(), ExtraArgs::default())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out.qoi", &output)?;
+    assert_eq!(output[..14], input[..14]);
+
+    let decoder = new_decoder("/tmp/s739_out.qoi".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+fn wav_fmt(channels: u16, bits: u16) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    [
+        &1u16.to_le_bytes()[..],
+        &channels.to_le_bytes(),
+        &44100u32.to_le_bytes(),
+        &(44100 * block_align as u32).to_le_bytes(),
+        &block_align.to_le_bytes(),
+        &bits.to_le_bytes(),
+    ]
+    .concat()
+}
+
+#[test]
+fn wav() -> Result<()> {
+    for (channels, bits) in [(2, 16), (1, 24)] {
+        let samples: Vec<u8> = (0..8192 * bits as usize / 8)
+            .map(|_| rng().random())
+            .collect();
+        let input = riff(
+            b"WAVE",
+            &[
+                (b"fmt ", wav_fmt(channels, bits)),
+                (b"LIST", b"INFOISFT\x05\x00\x00\x00test\x00".to_vec()),
+                (b"data", samples.clone()),
+            ],
+        );
+        std::fs::write("/tmp/s739_in.wav", &input)?;
+
+        let data = rand_string(500).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            channels: Some(vec![0]),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in.wav".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out.wav", &output)?;
+
+        // only the data chunk changes, and only in the first channel
+        assert_eq!(output.len(), input.len());
+        let data_start = input.len() - samples.len();
+        assert_eq!(output[..data_start], input[..data_start]);
+        let frame = (channels * bits / 8) as usize;
+        for (before, after) in input[data_start..]
+            .chunks(frame)
+            .zip(output[data_start..].chunks(frame))
+        {
+            assert_eq!(before[bits as usize / 8..], after[bits as usize / 8..]);
+        }
+
+        let decoder = new_decoder("/tmp/s739_out.wav".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+
+    let input = riff(b"WAVE", &[(b"fmt ", wav_fmt(1, 8)), (b"data", vec![0; 64])]);
+    std::fs::write("/tmp/s739_in_8bit.wav", input)?;
+    assert!(new_encoder("/tmp/s739_in_8bit.wav".into(), ExtraArgs::default()).is_err());
+
+    // fewer samples than the size header
+    let input = riff(
+        b"WAVE",
+        &[(b"fmt ", wav_fmt(1, 16)), (b"data", vec![0; 32])],
+    );
+    std::fs::write("/tmp/s739_in_short.wav", input)?;
+    let error = new_decoder("/tmp/s739_in_short.wav".into(), ExtraArgs::default()).err();
+    assert!(error.unwrap().to_string().contains("too short"));
+    Ok(())
+}
+
+fn crc(data: &[u8], poly: u16, width: u32) -> u16 {
+    let top = 1 << (width - 1);
+    let mask = ((1u32 << width) - 1) as u16;
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << (width - 8);
+        for _ in 0..8 {
+            crc = if crc & top != 0 {
+                crc << 1 ^ poly
+            } else {
+                crc << 1
+            } & mask;
+        }
+        crc
+    })
+}
+
+/// Byte-aligned FLAC with verbatim subframes and extra metadata blocks
+fn flac(channels: usize, bits: usize, samples: &[i32], blocks: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let frames = samples.len() / channels;
+    let mut info = Vec::new();
+    info.extend_from_slice(&4096u16.to_be_bytes());
+    info.extend_from_slice(&4096u16.to_be_bytes());
+    info.extend_from_slice(&[0; 6]);
+    let packed = (44100u64 << 44)
+        | ((channels as u64 - 1) << 41)
+        | ((bits as u64 - 1) << 36)
+        | frames as u64;
+    info.extend_from_slice(&packed.to_be_bytes());
+    info.extend_from_slice(&[0; 16]);
+
+    let mut buffer = b"fLaC".to_vec();
+    let blocks = [&[(0, info)][..], blocks].concat();
+    for (idx, (kind, data)) in blocks.iter().enumerate() {
+        buffer.push(kind | if idx + 1 == blocks.len() { 0x80 } else { 0 });
+        buffer.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        buffer.extend_from_slice(data);
+    }
+    for (number, block) in samples.chunks(4096 * channels).enumerate() {
+        let start = buffer.len();
+        let sample_size = if bits == 16 { 0b100 } else { 0b110 };
+        buffer.extend_from_slice(&[
+            0xFF,
+            0xF8,
+            0x70,
+            ((channels - 1) << 4 | sample_size << 1) as u8,
+            number as u8,
+        ]);
+        buffer.extend_from_slice(&((block.len() / channels) as u16 - 1).to_be_bytes());
+        buffer.push(crc(&buffer[start..], 0x07, 8) as u8);
+        for channel in 0..channels {
+            buffer.push(0b0000_0010);
+            for sample in block.iter().skip(channel).step_by(channels) {
+                buffer.extend_from_slice(&sample.to_be_bytes()[4 - bits / 8..]);
+            }
+        }
+        let crc = crc(&buffer[start..], 0x8005, 16);
+        buffer.extend_from_slice(&crc.to_be_bytes());
+    }
+    buffer
+}
+
+#[test]
+fn flac_lossless() -> Result<()> {
+    let comment = [
+        &4u32.to_le_bytes()[..],
+        b"s739",
+        &1u32.to_le_bytes(),
+        &11u32.to_le_bytes(),
+        b"TITLE=cover",
+    ]
+    .concat();
+    let picture = [&3u32.to_be_bytes()[..], b"fake picture block"].concat();
+    for (channels, bits) in [(2, 16), (1, 24)] {
+        let amplitude = (1 << (bits - 2)) as f64;
+        let samples: Vec<i32> = (0..10000 * channels)
+            .map(|idx| {
+                let t = (idx / channels) as f64 / 44100.0;
+                (amplitude * (t * 440.0 * std::f64::consts::TAU).sin()) as i32
+                    + rng().random_range(-64..64)
+            })
+            .collect();
+        let input = flac(
+            channels,
+            bits,
+            &samples,
+            &[(4, comment.clone()), (6, picture.clone()), (3, vec![0; 18])],
+        );
+        std::fs::write("/tmp/s739_in.flac", &input)?;
+
+        let data = rand_string(1000).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            channels: Some(vec![0]),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in.flac".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out.flac", &output)?;
+        // fixed predictors beat verbatim frames of the cover
+        assert!(output.len() < input.len());
+
+        let mut reader = claxon::FlacReader::new(std::io::Cursor::new(&output))?;
+        assert_eq!(reader.get_tag("TITLE").next(), Some("cover"));
+        let md5sum = reader.streaminfo().md5sum;
+        let decoded = reader.samples().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(decoded.len(), samples.len());
+        let mut md5 = md5::Context::new();
+        for (idx, (before, after)) in samples.iter().zip(&decoded).enumerate() {
+            md5.consume(&after.to_le_bytes()[..bits / 8]);
+            if idx % channels == 0 {
+                assert!((before - after).abs() <= 1);
+            } else {
+                assert_eq!(before, after);
+            }
+        }
+        assert_eq!(md5.compute().0, md5sum);
+        assert!(output.windows(picture.len()).any(|w| w == picture));
+
+        let decoder = new_decoder("/tmp/s739_out.flac".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn flac_top_bit() -> Result<()> {
+    let samples: Vec<i32> = (0..50000)
+        .map(|idx| (8000.0 * (idx as f64 / 44100.0 * 440.0 * std::f64::consts::TAU).sin()) as i32)
+        .collect();
+    std::fs::write("/tmp/s739_in_top.flac", flac(1, 16, &samples, &[]))?;
+
+    // few samples changed, so blocks still use fixed predictors
+    let data = rand_string(16).into_bytes();
+    let extra = ExtraArgs {
+        depth: 15,
+        ..Default::default()
+    };
+    let mut encoder = new_encoder("/tmp/s739_in_top.flac".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    let output = encoder.encode_image(ImageOptions::default())?;
+    std::fs::write("/tmp/s739_out_top.flac", &output)?;
+
+    let mut reader = claxon::FlacReader::new(std::io::Cursor::new(&output))?;
+    let decoded = reader.samples().collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(decoded.len(), samples.len());
+    assert!(decoded.iter().all(|sample| i16::try_from(*sample).is_ok()));
+    // only the sign bit differs
+    assert!(samples
+        .iter()
+        .zip(&decoded)
+        .all(|(before, after)| (before ^ after) & 0x7FFF == 0));
+
+    let decoder = new_decoder("/tmp/s739_out_top.flac".into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+#[test]
+fn y4m_planes() -> Result<()> {
+    let (width, height) = (64, 48);
+    let luma = width * height;
+    for (colorspace, sample, planes) in [("420jpeg", 1, vec![0]), ("420p10", 2, vec![1, 2])] {
+        let mut input =
+            format!("YUV4MPEG2 W{width} H{height} F25:1 Ip A1:1 C{colorspace} XYSCSS=x\n")
+                .into_bytes();
+        let mut frames = Vec::new();
+        for idx in 0..3 {
+            let header = input.len();
+            input.extend_from_slice(if idx == 1 { b"FRAME Ip\n" } else { b"FRAME\n" });
+            frames.push((header, input.len()));
+            input.extend((0..(luma + luma / 2) * sample).map(|idx| {
+                // keep 10-bit samples in range
+                if sample == 2 && idx % 2 == 1 {
+                    rng().random_range(0..4)
+                } else {
+                    rng().random()
+                }
+            }));
+        }
+        std::fs::write("/tmp/s739_in.y4m", &input)?;
+
+        let data = rand_string(300).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            channels: Some(planes.clone()),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in.y4m".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = encoder.encode_image(ImageOptions::default())?;
+        std::fs::write("/tmp/s739_out.y4m", &output)?;
+
+        // headers and unselected planes are untouched
+        assert_eq!(output.len(), input.len());
+        assert_eq!(output[..frames[0].0], input[..frames[0].0]);
+        for &(header, frame) in &frames {
+            let (y, uv) = (frame..frame + luma * sample, frame + luma * sample);
+            let end = frame + (luma + luma / 2) * sample;
+            if planes.contains(&0) {
+                assert_eq!(output[uv..end], input[uv..end]);
+            } else {
+                assert_eq!(output[y.clone()], input[y]);
+            }
+            assert_eq!(output[header..frame], input[header..frame]);
+        }
+
+        let decoder = new_decoder("/tmp/s739_out.y4m".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+
+    let mut mono = b"YUV4MPEG2 W8 H8 Cmono\nFRAME\n".to_vec();
+    mono.extend_from_slice(&[0; 64]);
+    std::fs::write("/tmp/s739_in_mono.y4m", mono)?;
+    let extra = ExtraArgs {
+        channels: Some(vec![1]),
+        ..Default::default()
+    };
+    assert!(new_encoder("/tmp/s739_in_mono.y4m".into(), extra).is_err());
+
+    // fewer samples than the size header
+    let mut tiny = b"YUV4MPEG2 W4 H4 C420jpeg\nFRAME\n".to_vec();
+    tiny.extend_from_slice(&[0; 24]);
+    std::fs::write("/tmp/s739_in_tiny.y4m", tiny)?;
+    let extra = ExtraArgs {
+        channels: Some(vec![0]),
+        ..Default::default()
+    };
+    let error = new_decoder("/tmp/s739_in_tiny.y4m".into(), extra).err();
+    assert!(error.unwrap().to_string().contains("too small"));
+    Ok(())
+}
+
+#[test]
+fn stream_encode() -> Result<()> {
+    for ext in ["png", "jpg"] {
+        let data = rand_string(1000).into_bytes();
+        let in_path = format!("/tmp/s739_in_stream_encode.{ext}");
+        let out_path = format!("/tmp/s739_out_stream_encode.{ext}");
+        image::DynamicImage::ImageRgb8(image::ImageBuffer::new(128, 128)).save(&in_path)?;
+
+        let mut encoder = new_encoder(in_path.into(), ExtraArgs::default())?;
+        let mut writer = encoder.writer(data.len())?;
+        for chunk in data.chunks(7) {
+            writer.write_all(chunk)?;
+        }
+        writer.finish()?;
+        std::fs::write(&out_path, encoder.encode_image(ImageOptions::default())?)?;
+
+        let decoder = new_decoder(out_path.into(), ExtraArgs::default())?;
+        assert_eq!(decoder.read_data()?, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn stream_decode() -> Result<()> {
+    for ext in ["png", "jpg"] {
+        let data = rand_string(1000).into_bytes();
+        let in_path = format!("/tmp/s739_in_stream.{ext}");
+        let out_path = format!("/tmp/s739_out_stream.{ext}");
+        image::DynamicImage::ImageRgb8(image::ImageBuffer::new(128, 128)).save(&in_path)?;
+
+        let mut encoder = new_encoder(in_path.into(), ExtraArgs::default())?;
+        encoder.write_data(&data)?;
+        std::fs::write(&out_path, encoder.encode_image(ImageOptions::default())?)?;
+
+        let decoder = new_decoder(out_path.into(), ExtraArgs::default())?;
+        let mut reader = decoder.reader()?;
+        let mut decoded_data = Vec::new();
+        let mut chunk = [0u8; 7];
+        loop {
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            decoded_data.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(decoded_data, data);
+    }
+    Ok(())
+}
+
+#[test]
+fn text() -> Result<()> {
+    let cover = "Lorem ipsum dolor sit amet, consectetur adipiscing elit.  \r\n\
+                 Sed do eiusmod tempor incididunt\t\n\
+                 \n\
+                 ut labore et dolore magna aliqua.";
+    std::fs::write("/tmp/s739_in.txt", cover)?;
+
+    // visible text, without trailing whitespace
+    let visible = |text: &str| -> Vec<String> {
+        text.lines()
+            .map(|line| {
+                line.replace(['\u{200B}', '\u{200C}'], "")
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    };
+
+    for mode in [Mode::ZeroWidth, Mode::Whitespace] {
+        let data = rand_string(200).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            mode,
+            ..Default::default()
+        };
+        let mut encoder = new_encoder("/tmp/s739_in.txt".into(), extra.clone())?;
+        encoder.write_data(&data)?;
+        let output = String::from_utf8(encoder.encode_image(ImageOptions::default())?)?;
+        std::fs::write("/tmp/s739_out.txt", &output)?;
+        assert_eq!(visible(&output), visible(cover));
+        assert_eq!(output.matches("\r\n").count(), 1);
+
+        // LSB mode looks for both, text has to be named then
+        for (mode, format) in [(mode, None), (Mode::Lsb, Some("text".into()))] {
+            let extra = ExtraArgs {
+                mode,
+                format,
+                ..extra.clone()
+            };
+            let decoder = new_decoder("/tmp/s739_out.txt".into(), extra)?;
+            assert_eq!(decoder.read_data()?, data);
+        }
+        assert!(new_decoder("/tmp/s739_out.txt".into(), ExtraArgs::default()).is_err());
+    }
+
+    let extra = ExtraArgs {
+        mode: Mode::Metadata,
+        format: Some("text".into()),
+        ..Default::default()
+    };
+    assert!(new_encoder("/tmp/s739_in.txt".into(), extra).is_err());
+    // plain text has no magic bytes, it isn't detected as a cover
+    let result = new_encoder("/tmp/s739_in.txt".into(), ExtraArgs::default());
+    assert!(result.is_err_and(|err| err.to_string() == "invalid image format"));
+    Ok(())
+}
+
+#[test]
+fn format_override_and_registry() -> Result<()> {
+    // detected as BMP by its magic bytes
+    std::fs::write(
+        "/tmp/s739_in_bm.txt",
+        "BM-text: not a bitmap, just a plain text",
+    )?;
+    let data = rand_string(20).into_bytes();
+    assert!(new_encoder("/tmp/s739_in_bm.txt".into(), ExtraArgs::default()).is_err());
+    let extra = ExtraArgs {
+        format: Some("text".into()),
+        ..Default::default()
+    };
+    let mut encoder = new_encoder("/tmp/s739_in_bm.txt".into(), extra.clone())?;
+    encoder.write_data(&data)?;
+    std::fs::write(
+        "/tmp/s739_out_bm.txt",
+        encoder.encode_image(ImageOptions::default())?,
+    )?;
+    let decoder = new_decoder("/tmp/s739_out_bm.txt".into(), extra)?;
+    assert_eq!(decoder.read_data()?, data);
+
+    let extra = ExtraArgs {
+        format: Some("unknown".into()),
+        ..Default::default()
+    };
+    assert!(new_encoder("/tmp/s739_in_bm.txt".into(), extra).is_err());
+
+    // library backend detected by its own magic, delegating to text
+    format::register(format::Format {
+        name: "bm-text",
+        extensions: &[],
+        detect: |buffer| buffer.starts_with(b"BM-text:"),
+        encoder: |buffer, extra| (format::find(buffer, Some("text"))?.encoder)(buffer, extra),
+        decoder: |buffer, extra| (format::find(buffer, Some("text"))?.decoder)(buffer, extra),
+        from_pixels: None,
+        multi_frame: None,
+    });
+    let mut encoder = new_encoder("/tmp/s739_in_bm.txt".into(), ExtraArgs::default())?;
+    encoder.write_data(&data)?;
+    std::fs::write(
+        "/tmp/s739_out_bm.txt",
+        encoder.encode_image(ImageOptions::default())?,
+    )?;
+    let decoder = new_decoder("/tmp/s739_out_bm.txt".into(), ExtraArgs::default())?;
+    assert_eq!(decoder.read_data()?, data);
+    Ok(())
+}
+
+#[test]
+fn convert_output() -> Result<()> {
+    let mut rng = rng();
+    let rgb = image::RgbImage::from_fn(64, 48, |_, _| image::Rgb(rng.random()));
+    let rgba = image::RgbaImage::from_fn(64, 48, |_, _| image::Rgba(rng.random()));
+    image::DynamicImage::ImageRgb8(rgb).save("/tmp/s739_convert_in.jpg")?;
+    image::DynamicImage::ImageRgba8(rgba).save("/tmp/s739_convert_in.png")?;
+
+    for cover in ["/tmp/s739_convert_in.jpg", "/tmp/s739_convert_in.png"] {
+        for output in ["png", "bmp", "webp", "tiff", "pnm", "qoi"] {
+            let data = rand_string(200).into_bytes();
+            let extra = ExtraArgs {
+                key: Some(rand_string(16)),
+                ..Default::default()
+            };
+            let mut encoder = new_encoder_to(
+                cover.into(),
+                Some(output),
+                extra.clone(),
+                &ImageOptions::default(),
+            )?;
+            encoder.write_data(&data)?;
+            let buffer = encoder.encode_image(ImageOptions::default())?;
+            let out_path = format!("/tmp/s739_convert_out.{output}");
+            std::fs::write(&out_path, &buffer)?;
+
+            assert_eq!(format::find(&buffer, None)?.name, output);
+            let decoder = new_decoder(out_path.into(), extra)?;
+            assert_eq!(decoder.read_data()?, data);
+        }
+    }
+
+    // lossy outputs and covers without pixels
+    let result = new_encoder_to(
+        "/tmp/s739_convert_in.png".into(),
+        Some("gif"),
+        ExtraArgs::default(),
+        &ImageOptions::default(),
+    );
+    assert!(result.is_err_and(|err| err.to_string().contains("lossy")));
+    let result = new_encoder_to(
+        "/tmp/s739_convert_in.png".into(),
+        Some("wav"),
+        ExtraArgs::default(),
+        &ImageOptions::default(),
+    );
+    assert!(result.is_err_and(|err| err.to_string().contains("can't be produced from pixels")));
+    std::fs::write("/tmp/s739_convert_in.txt", "plain text")?;
+    assert!(new_encoder_to(
+        "/tmp/s739_convert_in.txt".into(),
+        Some("png"),
+        ExtraArgs::default(),
+        &ImageOptions::default(),
+    )
+    .is_err());
+    let extra = ExtraArgs {
+        mode: Mode::Metadata,
+        ..Default::default()
+    };
+    assert!(new_encoder_to(
+        "/tmp/s739_convert_in.jpg".into(),
+        Some("png"),
+        extra,
+        &ImageOptions::default(),
+    )
+    .is_err());
+
+    // animations keep all their frames only in their own format
+    std::fs::write("/tmp/s739_convert_in_apng.png", apng(false)?)?;
+    let result = new_encoder_to(
+        "/tmp/s739_convert_in_apng.png".into(),
+        Some("bmp"),
+        ExtraArgs::default(),
+        &ImageOptions::default(),
+    );
+    assert!(result.is_err_and(|err| err.to_string().contains("first frame")));
+    assert!(new_encoder_to(
+        "/tmp/s739_convert_in_apng.png".into(),
+        Some("png"),
+        ExtraArgs::default(),
+        &ImageOptions::default(),
+    )
+    .is_ok());
+    Ok(())
+}
+
+#[test]
+fn jpeg_from_pixels() -> Result<()> {
+    let mut rng = rng();
+    let rgba = image::RgbaImage::from_fn(128, 96, |_, _| image::Rgba(rng.random()));
+    let gray = image::GrayImage::from_fn(128, 96, |_, _| image::Luma(rng.random()));
+    image::DynamicImage::ImageRgba8(rgba).save("/tmp/s739_pixels_in_rgba.png")?;
+    image::DynamicImage::ImageLuma8(gray).save("/tmp/s739_pixels_in_gray.png")?;
+
+    let mut sizes = Vec::new();
+    for (cover, components) in [
+        ("/tmp/s739_pixels_in_rgba.png", 3),
+        ("/tmp/s739_pixels_in_gray.png", 1),
+    ] {
+        for quality in [30, 90] {
+            let data = rand_string(300).into_bytes();
+            let extra = ExtraArgs {
+                key: Some(rand_string(16)),
+                ..Default::default()
+            };
+            let mut image_opts = ImageOptions::default();
+            image_opts.jpeg.quality = Some(quality);
+            let mut encoder =
+                new_encoder_to(cover.into(), Some("jpeg"), extra.clone(), &image_opts)?;
+            encoder.write_data(&data)?;
+            let buffer = encoder.encode_image(image_opts)?;
+            std::fs::write("/tmp/s739_pixels_out.jpg", &buffer)?;
+
+            let image = image::load_from_memory(&buffer)?;
+            assert_eq!((image.width(), image.height()), (128, 96));
+            assert_eq!(image.color().channel_count(), components);
+            let decoder = new_decoder("/tmp/s739_pixels_out.jpg".into(), extra)?;
+            assert_eq!(decoder.read_data()?, data);
+            sizes.push(buffer.len());
+        }
+    }
+    // higher quality keeps more coefs
+    assert!(sizes[0] < sizes[1] && sizes[2] < sizes[3]);
+    Ok(())
+}
+
+/// 8-bit quantization tables by id, in the zigzag order of the DQT segment
+fn jpeg_quant_tables(buffer: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    jpeg_header(buffer)
+        .into_iter()
+        .filter(|(marker, _)| *marker == 0xDB)
+        .flat_map(|(_, data)| {
+            data.chunks(65)
+                .map(|table| (table[0] & 0x0F, table[1..].to_vec()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn jpeg_quantization_options() -> Result<()> {
+    let mut rng = rng();
+    let rgb = image::RgbImage::from_fn(128, 96, |_, _| image::Rgb(rng.random()));
+    image::DynamicImage::ImageRgb8(rgb).save("/tmp/s739_quant_in.png")?;
+
+    let roundtrip = |cover: &str, image_opts: ImageOptions| -> Result<Vec<u8>> {
+        let data = rand_string(100).into_bytes();
+        let extra = ExtraArgs {
+            key: Some(rand_string(16)),
+            ..Default::default()
+        };
+        let mut encoder = new_encoder_to(cover.into(), Some("jpeg"), extra.clone(), &image_opts)?;
+        encoder.write_data(&data)?;
+        let buffer = encoder.encode_image(image_opts)?;
+        std::fs::write("/tmp/s739_quant_out.jpg", &buffer)?;
+        let decoder = new_decoder("/tmp/s739_quant_out.jpg".into(), extra)?;
+        assert_eq!(decoder.read_data()?, data);
+        Ok(buffer)
+    };
+
+    // custom tables are kept as they are without a quality, one table for both
+    let mut image_opts = ImageOptions::default();
+    image_opts.jpeg.quant_tables = vec![[12; 64]];
+    image_opts.jpeg.subsampling = Some(Subsampling::S444);
+    image_opts.jpeg.trellis = Some(false);
+    let buffer = roundtrip("/tmp/s739_quant_in.png", image_opts.clone())?;
+    let tables = jpeg_quant_tables(&buffer);
+    assert_eq!(tables.len(), 2);
+    assert!(tables.iter().all(|(_, table)| table == &[12; 64]));
+    let (_, sof) = jpeg_header(&buffer)
+        .into_iter()
+        .find(|(marker, _)| matches!(marker, 0xC0 | 0xC2))
+        .unwrap();
+    assert!(sof[6..].chunks(3).all(|comp| comp[1] == 0x11));
+
+    // an explicit quality scales them, 25 doubles
+    image_opts.jpeg.quality = Some(25);
+    let buffer = roundtrip("/tmp/s739_quant_in.png", image_opts.clone())?;
+    assert!(jpeg_quant_tables(&buffer)
+        .iter()
+        .all(|(_, table)| table == &[24; 64]));
+
+    image_opts.jpeg.quant_tables = vec![[12; 64]; 3];
+    assert!(roundtrip("/tmp/s739_quant_in.png", image_opts).is_err());
+
+    // JPEG cover quantized again only on request
+    let mut image_opts = ImageOptions::default();
+    image_opts.jpeg.quality = Some(95);
+    std::fs::write(
+        "/tmp/s739_quant_in.jpg",
+        roundtrip("/tmp/s739_quant_in.png", image_opts)?,
+    )?;
+    let source_tables = jpeg_quant_tables(&std::fs::read("/tmp/s739_quant_in.jpg")?);
+    let kept = roundtrip("/tmp/s739_quant_in.jpg", ImageOptions::default())?;
+    assert_eq!(jpeg_quant_tables(&kept), source_tables);
+    // quality would be ignored without requantizing
+    let mut image_opts = ImageOptions::default();
+    image_opts.jpeg.quality = Some(20);
+    assert!(roundtrip("/tmp/s739_quant_in.jpg", image_opts.clone()).is_err());
+    image_opts.jpeg.requantize = true;
+    let requantized = roundtrip("/tmp/s739_quant_in.jpg", image_opts.clone())?;
+    assert_ne!(jpeg_quant_tables(&requantized), source_tables);
+    assert!(requantized.len() < kept.len());
+
+    // and with a non-JPEG output, cover format included
+    for output in [Some("png"), None] {
+        let result = new_encoder_to(
+            "/tmp/s739_quant_in.png".into(),
+            output,
+            ExtraArgs::default(),
+            &image_opts,
+        );
+        assert!(result.is_err());
+    }
+    Ok(())
+}